@@ -0,0 +1,80 @@
+use crate::apu::ApuRegisters;
+use crate::mem::memory::{request_sound_dma, InternalMemory};
+
+const FIFO_LENGTH: usize = 32;
+
+/// one of the two 8-bit Direct Sound channels, the CPU or DMA fills it
+/// and the selected timer takes a sample out every time it overflows
+pub struct DirectSoundFifo {
+    samples: [i8; FIFO_LENGTH],
+    read: usize,
+    len: usize,
+    // the sample being played, this stays the same if the FIFO runs dry
+    pub current: i8,
+}
+impl DirectSoundFifo {
+    pub fn new() -> Self {
+        Self {
+            samples: [0; FIFO_LENGTH],
+            read: 0,
+            len: 0,
+            current: 0,
+        }
+    }
+
+    pub fn push(&mut self, data: u8) {
+        if self.len == FIFO_LENGTH {
+            return;
+        }
+        let write = (self.read + self.len) % FIFO_LENGTH;
+        self.samples[write] = data as i8;
+        self.len += 1;
+    }
+    pub fn pop(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+        self.current = self.samples[self.read];
+        self.read = (self.read + 1) % FIFO_LENGTH;
+        self.len -= 1;
+    }
+    pub fn reset(&mut self) {
+        self.read = 0;
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// sound DMA gets asked for more data once it is half empty
+    pub fn needs_refill(&self) -> bool {
+        self.len <= FIFO_LENGTH / 2
+    }
+}
+impl Default for DirectSoundFifo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// timers 0 and 1 are the only ones which can drive the FIFOs,
+/// SOUNDCNT_H says which FIFO listens to which timer
+pub fn fifo_timer_overflow(mem: &mut InternalMemory, overflows: [bool; 4]) {
+    let soundcnt_h = mem.sys_read_u16(ApuRegisters::SoundCntH as u32);
+
+    for fifo in 0..2 {
+        let timer = (soundcnt_h >> (10 + fifo * 4)) as usize & 1;
+        if !overflows[timer] {
+            continue;
+        }
+
+        mem.fifos[fifo].pop();
+        if mem.fifos[fifo].needs_refill() {
+            let fifo_address = ApuRegisters::FifoA as u32 + fifo as u32 * 4;
+            request_sound_dma(mem, fifo_address);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+const CPU_FREQUENCY: f64 = 16_777_216.;
+// a quarter of a second is plenty of slack for whoever is pulling the samples
+const BUFFER_SECONDS_DIVISOR: usize = 4;
+
+/// stereo frames stored interleaved (left, right), when nobody is draining it
+/// the oldest frames get thrown away so it never grows
+pub struct SampleBuffer {
+    samples: Vec<i16>,
+    read: usize,
+    len: usize,
+}
+impl SampleBuffer {
+    fn new(frames: usize) -> Self {
+        Self {
+            samples: vec![0; frames * 2],
+            read: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, left: i16, right: i16) {
+        let capacity = self.samples.len();
+        if self.len == capacity {
+            self.read = (self.read + 2) % capacity;
+            self.len -= 2;
+        }
+
+        let write = (self.read + self.len) % capacity;
+        self.samples[write] = left;
+        self.samples[write + 1] = right;
+        self.len += 2;
+    }
+
+    /// only whole frames are copied, the amount of i16's written is returned
+    pub fn drain(&mut self, out: &mut [i16]) -> usize {
+        let capacity = self.samples.len();
+        let amount = self.len.min(out.len() & !1);
+
+        for sample in out.iter_mut().take(amount) {
+            *sample = self.samples[self.read];
+            self.read = (self.read + 1) % capacity;
+        }
+        self.len -= amount;
+        return amount;
+    }
+
+    /// the number of stereo frames waiting to be drained
+    pub fn frames(&self) -> usize {
+        self.len / 2
+    }
+}
+
+/// takes the biased GBA output at whatever rate SOUNDBIAS asks for and averages
+/// it down (or up) to the host's sample rate
+pub struct Mixer {
+    sample_rate: u32,
+    host_period: f64,
+    host_elapsed: f64,
+    sum: (f64, f64),
+
+    gba_timer: u32,
+    pub current: (i16, i16),
+    pub buffer: SampleBuffer,
+}
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            host_period: CPU_FREQUENCY / sample_rate as f64,
+            host_elapsed: 0.,
+            sum: (0., 0.),
+
+            gba_timer: 0,
+            current: (0, 0),
+            buffer: SampleBuffer::new(sample_rate as usize / BUFFER_SECONDS_DIVISOR),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        *self = Mixer::new(sample_rate);
+    }
+    /// nudges how many samples get made per emulated second, a ratio above 1
+    /// makes more of them. Used to stop whatever is playing them from drifting
+    pub fn set_rate_ratio(&mut self, ratio: f64) {
+        self.host_period = CPU_FREQUENCY / (self.sample_rate as f64 * ratio);
+    }
+
+    /// returns true whenever the GBA itself would output a new sample
+    pub fn tick_gba_timer(&mut self, cycles: u32, soundbias: u16) -> bool {
+        let period = sample_period(soundbias);
+
+        self.gba_timer += cycles;
+        if self.gba_timer < period {
+            return false;
+        }
+        self.gba_timer %= period;
+        return true;
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut remaining = cycles as f64;
+        while remaining > 0. {
+            // the period can shrink under host_elapsed when the ratio changes
+            let step = remaining.min((self.host_period - self.host_elapsed).max(0.));
+            self.sum.0 += self.current.0 as f64 * step;
+            self.sum.1 += self.current.1 as f64 * step;
+            self.host_elapsed += step;
+            remaining -= step;
+
+            if self.host_elapsed < self.host_period {
+                continue;
+            }
+            let left = self.sum.0 / self.host_elapsed;
+            let right = self.sum.1 / self.host_elapsed;
+            self.buffer.push(left as i16, right as i16);
+
+            self.host_elapsed = 0.;
+            self.sum = (0., 0.);
+        }
+    }
+}
+
+/// the cycles between the GBA's own samples,
+/// 9-bit resolution is 32.768kHz and each bit less doubles that
+pub fn sample_period(soundbias: u16) -> u32 {
+    let resolution = (soundbias >> 14) & 0x3;
+    return 512 >> resolution;
+}
+
+/// the bias centres the output in the 10-bit DAC range, anything outside of it
+/// clips and lower resolutions lose their bottom bits
+pub fn apply_bias(sample: i32, soundbias: u16) -> i16 {
+    let bias = (soundbias & 0x3FE) as i32;
+    let resolution = (soundbias >> 14) & 0x3;
+
+    let biased = (sample + bias).clamp(0, 0x3FF);
+    let quantized = biased & !((1 << resolution) - 1);
+
+    // back around 0 and scaled up from 10-bit to 16-bit
+    return ((quantized - bias) << 5) as i16;
+}
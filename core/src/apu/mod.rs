@@ -0,0 +1,360 @@
+mod square;
+mod wave;
+mod noise;
+mod fifo;
+mod mixer;
+mod scope;
+
+use crate::mem::bus::Bus;
+use crate::mem::memory::InternalMemory;
+use noise::NoiseChannel;
+use square::SquareChannel;
+use wave::WaveChannel;
+pub use fifo::{fifo_timer_overflow, DirectSoundFifo};
+pub use mixer::{sample_period, Mixer, SampleBuffer, DEFAULT_SAMPLE_RATE};
+use mixer::apply_bias;
+pub use scope::{Scope, SCOPE_LENGTH};
+
+pub enum ApuRegisters {
+    // tone & sweep
+    Sound1CntL = 0x4000060,
+    Sound1CntH = 0x4000062,
+    Sound1CntX = 0x4000064,
+
+    // tone
+    Sound2CntL = 0x4000068,
+    Sound2CntH = 0x400006C,
+
+    // wave output
+    Sound3CntL = 0x4000070,
+    Sound3CntH = 0x4000072,
+    Sound3CntX = 0x4000074,
+    Sound3Ram0L = 0x4000090,
+    Sound3Ram0H = 0x4000092,
+
+    // noise
+    Sound4CntL = 0x4000078,
+    Sound4CntH = 0x400007C,
+
+    // Sound Control Registers
+    SoundCntL = 0x4000080,
+    SoundCntH = 0x4000082,
+    SoundCntX = 0x4000084,
+    SoundBias = 0x4000088,
+
+    // DMA sound
+    FifoA = 0x40000A0,
+    FifoB = 0x40000A4,
+}
+
+/// everything that can be heard, in the order the bits for them appear in SOUNDCNT_L/H
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoundChannel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+    FifoA,
+    FifoB,
+}
+pub const SOUND_CHANNELS: [SoundChannel; 6] = [
+    SoundChannel::Square1,
+    SoundChannel::Square2,
+    SoundChannel::Wave,
+    SoundChannel::Noise,
+    SoundChannel::FifoA,
+    SoundChannel::FifoB,
+];
+impl SoundChannel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SoundChannel::Square1 => "Square 1 (sweep)",
+            SoundChannel::Square2 => "Square 2",
+            SoundChannel::Wave => "Wave",
+            SoundChannel::Noise => "Noise",
+            SoundChannel::FifoA => "FIFO A",
+            SoundChannel::FifoB => "FIFO B",
+        }
+    }
+}
+
+// the frame sequencer runs at 512Hz, which is every 2^24 / 512 cycles
+const CYCLES_PER_SEQUENCER_STEP: u32 = 0x8000;
+
+/// shared by the two square channels and the noise channel, the bits
+/// are in the same place for all of them (8..=15 of the register)
+#[derive(Default)]
+struct Envelope {
+    volume: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+}
+impl Envelope {
+    fn restart(&mut self, register: u16) {
+        self.volume = (register >> 12) as u8 & 0xF;
+        self.increase = (register >> 11) & 1 == 1;
+        self.period = (register >> 8) as u8 & 0x7;
+        self.timer = self.period;
+    }
+    fn clock(&mut self) {
+        // a period of 0 means the envelope is turned off
+        if self.period == 0 {
+            return;
+        }
+        self.timer -= 1;
+        if self.timer != 0 {
+            return;
+        }
+
+        self.timer = self.period;
+        match self.increase {
+            true => if self.volume < 15 { self.volume += 1 },
+            false => if self.volume > 0 { self.volume -= 1 },
+        }
+    }
+}
+/// the DAC is off whenever the volume is 0 and it isn't going to increase,
+/// this turns the channel off straight away
+fn dac_enabled(register: u16) -> bool {
+    register & 0xF800 != 0
+}
+
+#[derive(Default)]
+struct LengthCounter {
+    remaining: u16,
+    enabled: bool,
+}
+impl LengthCounter {
+    /// returns true if the channel should be turned off
+    fn clock(&mut self) -> bool {
+        if !self.enabled || self.remaining == 0 {
+            return false;
+        }
+        self.remaining -= 1;
+        return self.remaining == 0;
+    }
+}
+
+pub struct Apu {
+    pub square1: SquareChannel,
+    pub square2: SquareChannel,
+    pub wave: WaveChannel,
+    pub noise: NoiseChannel,
+    pub mixer: Mixer,
+    pub scopes: [Scope; 6],
+
+    // these only change what gets mixed, the channels still run underneath
+    muted: [bool; 6],
+    soloed: [bool; 6],
+
+    sequencer_cycles: u32,
+    sequencer_step: u8,
+}
+impl Apu {
+    pub fn new() -> Self {
+        use ApuRegisters::*;
+        Self {
+            square1: SquareChannel::new(Some(Sound1CntL as u32), Sound1CntH as u32, Sound1CntX as u32),
+            square2: SquareChannel::new(None, Sound2CntL as u32, Sound2CntH as u32),
+            wave: WaveChannel::new(),
+            noise: NoiseChannel::new(),
+            mixer: Mixer::new(DEFAULT_SAMPLE_RATE),
+            scopes: std::array::from_fn(|_| Scope::new()),
+
+            muted: [false; 6],
+            soloed: [false; 6],
+
+            sequencer_cycles: 0,
+            sequencer_step: 0,
+        }
+    }
+
+    pub fn set_muted(&mut self, channel: SoundChannel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+    pub fn is_muted(&self, channel: SoundChannel) -> bool {
+        self.muted[channel as usize]
+    }
+    pub fn set_soloed(&mut self, channel: SoundChannel, soloed: bool) {
+        self.soloed[channel as usize] = soloed;
+    }
+    pub fn is_soloed(&self, channel: SoundChannel) -> bool {
+        self.soloed[channel as usize]
+    }
+    /// once anything is soloed only the soloed channels play, mute is ignored for them
+    fn audible(&self, channel: usize) -> bool {
+        match self.soloed.iter().any(|s| *s) {
+            true => self.soloed[channel],
+            false => !self.muted[channel],
+        }
+    }
+
+    /// what a channel is outputting before any of the volume controls,
+    /// the PSG channels are within ±15 and the FIFOs are 8-bit
+    pub fn channel_output(&self, channel: SoundChannel, mem: &InternalMemory) -> i16 {
+        match channel {
+            SoundChannel::Square1 => self.square1.output(),
+            SoundChannel::Square2 => self.square2.output(),
+            SoundChannel::Wave => self.wave.output(),
+            SoundChannel::Noise => self.noise.output(),
+            SoundChannel::FifoA => mem.fifos[0].current as i16,
+            SoundChannel::FifoB => mem.fifos[1].current as i16,
+        }
+    }
+
+    /// the PSG channels combined as (left, right), each channel is only
+    /// added to the sides SOUNDCNT_L has enabled and then scaled by its master volume
+    pub fn psg_output(&self, mem: &InternalMemory) -> (i16, i16) {
+        let soundcnt_l = mem.sys_read_u16(ApuRegisters::SoundCntL as u32);
+        let outputs = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+
+        let (mut left, mut right) = (0, 0);
+        for (i, output) in outputs.iter().enumerate() {
+            if !self.audible(i) {
+                continue;
+            }
+            if (soundcnt_l >> (8 + i)) & 1 == 1 {
+                right += output;
+            }
+            if (soundcnt_l >> (12 + i)) & 1 == 1 {
+                left += output;
+            }
+        }
+
+        let right_volume = (soundcnt_l & 0x7) as i16 + 1;
+        let left_volume = ((soundcnt_l >> 4) & 0x7) as i16 + 1;
+        return (left * left_volume, right * right_volume);
+    }
+
+    /// the two FIFO channels as (left, right), SOUNDCNT_H decides
+    /// if each one is at 50% or 100% and which sides it plays on
+    pub fn direct_sound_output(&self, mem: &InternalMemory) -> (i16, i16) {
+        let soundcnt_h = mem.sys_read_u16(ApuRegisters::SoundCntH as u32);
+
+        let (mut left, mut right) = (0, 0);
+        for fifo in 0..2 {
+            if !self.audible(SoundChannel::FifoA as usize + fifo) {
+                continue;
+            }
+            let sample = mem.fifos[fifo].current as i16;
+            let sample = match (soundcnt_h >> (2 + fifo)) & 1 == 1 {
+                true => sample * 4,
+                false => sample * 2,
+            };
+
+            if (soundcnt_h >> (8 + fifo * 4)) & 1 == 1 {
+                right += sample;
+            }
+            if (soundcnt_h >> (9 + fifo * 4)) & 1 == 1 {
+                left += sample;
+            }
+        }
+        return (left, right);
+    }
+
+    /// everything combined into what the DAC would output, SOUNDCNT_H sets
+    /// how loud the PSG is compared to the FIFOs
+    fn mix_sample(&self, mem: &InternalMemory) -> (i16, i16) {
+        let soundcnt_x = mem.sys_read_u16(ApuRegisters::SoundCntX as u32);
+        if (soundcnt_x >> 7) & 1 == 0 {
+            return (0, 0);
+        }
+        let soundcnt_h = mem.sys_read_u16(ApuRegisters::SoundCntH as u32);
+        let soundbias = mem.sys_read_u16(ApuRegisters::SoundBias as u32);
+
+        // 0 = 25%, 1 = 50%, 2 = 100% and 3 is prohibited
+        let psg_shift = match soundcnt_h & 0x3 {
+            0 => 2,
+            1 => 1,
+            _ => 0,
+        };
+        let (psg_left, psg_right) = self.psg_output(mem);
+        let (fifo_left, fifo_right) = self.direct_sound_output(mem);
+
+        let left = (psg_left >> psg_shift) as i32 + fifo_left as i32;
+        let right = (psg_right >> psg_shift) as i32 + fifo_right as i32;
+        return (apply_bias(left, soundbias), apply_bias(right, soundbias));
+    }
+
+    fn clock_sequencer(&mut self, mem: &mut InternalMemory) {
+        // length counters are 256Hz, sweep is 128Hz and the envelope 64Hz
+        if self.sequencer_step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.sequencer_step == 2 || self.sequencer_step == 6 {
+            self.square1.clock_sweep(mem);
+        }
+        if self.sequencer_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.sequencer_step = (self.sequencer_step + 1) % 8;
+    }
+}
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn tick_apu(apu: &mut Apu, bus: &mut Bus, cycles: u32) {
+    let mem = &mut bus.mem;
+    let soundcnt_x = mem.sys_read_u16(ApuRegisters::SoundCntX as u32);
+
+    // master enable is off, none of the PSG channels can run
+    match (soundcnt_x >> 7) & 1 == 1 {
+        true => tick_channels(apu, mem, cycles),
+        false => {
+            apu.square1.enabled = false;
+            apu.square2.enabled = false;
+            apu.wave.enabled = false;
+            apu.noise.enabled = false;
+        }
+    }
+
+    // the bottom 4 bits are read only and show which channels are playing
+    let status =
+        (apu.square1.enabled as u16) << 0 |
+        (apu.square2.enabled as u16) << 1 |
+        (apu.wave.enabled as u16) << 2 |
+        (apu.noise.enabled as u16) << 3;
+    mem.sys_write_u16(ApuRegisters::SoundCntX as u32, (soundcnt_x & !0xF) | status);
+
+    let soundbias = mem.sys_read_u16(ApuRegisters::SoundBias as u32);
+    if apu.mixer.tick_gba_timer(cycles, soundbias) {
+        apu.mixer.current = apu.mix_sample(mem);
+        for channel in SOUND_CHANNELS {
+            let output = apu.channel_output(channel, mem);
+            apu.scopes[channel as usize].push(output);
+        }
+    }
+    apu.mixer.tick(cycles);
+}
+
+fn tick_channels(apu: &mut Apu, mem: &mut InternalMemory, cycles: u32) {
+    apu.square1.check_trigger(mem);
+    apu.square2.check_trigger(mem);
+    apu.wave.check_trigger(mem);
+    apu.noise.check_trigger(mem);
+
+    apu.sequencer_cycles += cycles;
+    while apu.sequencer_cycles >= CYCLES_PER_SEQUENCER_STEP {
+        apu.sequencer_cycles -= CYCLES_PER_SEQUENCER_STEP;
+        apu.clock_sequencer(mem);
+    }
+
+    apu.square1.tick(mem, cycles);
+    apu.square2.tick(mem, cycles);
+    apu.wave.tick(mem, cycles);
+    apu.noise.tick(mem, cycles);
+}
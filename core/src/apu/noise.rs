@@ -0,0 +1,104 @@
+use crate::apu::{dac_enabled, ApuRegisters, Envelope, LengthCounter};
+use crate::mem::memory::InternalMemory;
+
+pub struct NoiseChannel {
+    pub enabled: bool,
+
+    timer: u32,
+    lfsr: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+}
+impl NoiseChannel {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            timer: 0,
+            lfsr: 0x7FFF,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    pub fn check_trigger(&mut self, mem: &mut InternalMemory) {
+        let envelope_reg = mem.sys_read_u16(ApuRegisters::Sound4CntL as u32);
+        let frequency_reg = mem.sys_read_u16(ApuRegisters::Sound4CntH as u32);
+
+        self.length.enabled = (frequency_reg >> 14) & 1 == 1;
+        if !dac_enabled(envelope_reg) {
+            self.enabled = false;
+        }
+        if (frequency_reg >> 15) & 1 == 0 {
+            return;
+        }
+        mem.sys_write_u16(ApuRegisters::Sound4CntH as u32, frequency_reg & 0x7FFF);
+
+        self.enabled = dac_enabled(envelope_reg);
+        self.length.remaining = 64 - (envelope_reg & 0x3F);
+        self.envelope.restart(envelope_reg);
+        self.timer = period(frequency_reg);
+
+        // 7 bit mode starts at 0x7F, 15 bit at 0x7FFF
+        self.lfsr = match (frequency_reg >> 3) & 1 == 1 {
+            true => 0x7F,
+            false => 0x7FFF,
+        };
+    }
+
+    pub fn tick(&mut self, mem: &InternalMemory, mut cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+
+        let frequency_reg = mem.sys_read_u16(ApuRegisters::Sound4CntH as u32);
+        let is_7_bit = (frequency_reg >> 3) & 1 == 1;
+        let period = period(frequency_reg);
+
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = period;
+
+            let xor = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            if is_7_bit {
+                self.lfsr &= !(1 << 6);
+                self.lfsr |= xor << 6;
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let volume = self.envelope.volume as i16;
+        match self.lfsr & 1 == 0 {
+            true => volume,
+            false => -volume,
+        }
+    }
+}
+
+/// the dividing ratio r is treated as 0.5 when it's 0, everything is then multiplied
+/// by 4 as the GBA runs at 16MHz rather than the gameboy's 4MHz
+fn period(frequency_reg: u16) -> u32 {
+    let ratio = frequency_reg as u32 & 0x7;
+    let shift = (frequency_reg >> 4) as u32 & 0xF;
+    let divisor = match ratio {
+        0 => 8,
+        _ => ratio * 16,
+    };
+
+    return (divisor << shift) * 4;
+}
@@ -0,0 +1,32 @@
+// at the 32kHz of the default SOUNDBIAS this is about 30ms of history
+pub const SCOPE_LENGTH: usize = 1024;
+
+/// the last few outputs of a single channel, kept for anything
+/// which wants to draw them
+pub struct Scope {
+    samples: [i16; SCOPE_LENGTH],
+    write: usize,
+}
+impl Scope {
+    pub fn new() -> Self {
+        Self {
+            samples: [0; SCOPE_LENGTH],
+            write: 0,
+        }
+    }
+
+    pub fn push(&mut self, sample: i16) {
+        self.samples[self.write] = sample;
+        self.write = (self.write + 1) % SCOPE_LENGTH;
+    }
+
+    /// oldest sample first
+    pub fn samples(&self) -> impl Iterator<Item = i16> + '_ {
+        (0..SCOPE_LENGTH).map(|i| self.samples[(self.write + i) % SCOPE_LENGTH])
+    }
+}
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,159 @@
+use crate::apu::{dac_enabled, Envelope, LengthCounter};
+use crate::mem::memory::InternalMemory;
+
+const DUTY_PATTERNS: [[bool; 8]; 4] = [
+    [false, false, false, false, false, false, false, true], // 12.5%
+    [true, false, false, false, false, false, false, true], // 25%
+    [true, false, false, false, false, true, true, true], // 50%
+    [false, true, true, true, true, true, true, false], // 75%
+];
+
+pub struct SquareChannel {
+    pub enabled: bool,
+
+    // only channel 1 has a sweep register
+    sweep_address: Option<u32>,
+    duty_address: u32,
+    frequency_address: u32,
+
+    timer: u32,
+    duty: usize,
+    duty_step: usize,
+    envelope: Envelope,
+    length: LengthCounter,
+
+    sweep_enabled: bool,
+    sweep_timer: u8,
+    shadow_frequency: u16,
+}
+impl SquareChannel {
+    pub fn new(sweep_address: Option<u32>, duty_address: u32, frequency_address: u32) -> Self {
+        Self {
+            enabled: false,
+            sweep_address,
+            duty_address,
+            frequency_address,
+
+            timer: 0,
+            duty: 0,
+            duty_step: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+
+            sweep_enabled: false,
+            sweep_timer: 0,
+            shadow_frequency: 0,
+        }
+    }
+
+    /// bit 15 of the frequency register restarts the sound, it is write only
+    /// so it gets cleared once it has been seen
+    pub fn check_trigger(&mut self, mem: &mut InternalMemory) {
+        let frequency_reg = mem.sys_read_u16(self.frequency_address);
+        let duty_reg = mem.sys_read_u16(self.duty_address);
+
+        // changing these doesn't need a restart
+        self.duty = (duty_reg >> 6) as usize & 0x3;
+        self.length.enabled = (frequency_reg >> 14) & 1 == 1;
+        if !dac_enabled(duty_reg) {
+            self.enabled = false;
+        }
+        if (frequency_reg >> 15) & 1 == 0 {
+            return;
+        }
+        mem.sys_write_u16(self.frequency_address, frequency_reg & 0x7FFF);
+
+        self.enabled = dac_enabled(duty_reg);
+        self.length.remaining = 64 - (duty_reg & 0x3F);
+        self.envelope.restart(duty_reg);
+
+        let frequency = frequency_reg & 0x7FF;
+        self.timer = (2048 - frequency as u32) * 16;
+        self.duty_step = 0;
+
+        let Some(sweep_address) = self.sweep_address else { return; };
+        let sweep = mem.sys_read_u16(sweep_address);
+        let sweep_time = (sweep >> 4) as u8 & 0x7;
+        let sweep_shift = sweep & 0x7;
+
+        self.shadow_frequency = frequency;
+        self.sweep_timer = sweep_time;
+        self.sweep_enabled = sweep_time != 0 || sweep_shift != 0;
+        if sweep_shift != 0 && self.next_sweep_frequency(sweep) > 0x7FF {
+            self.enabled = false;
+        }
+    }
+
+    pub fn tick(&mut self, mem: &InternalMemory, mut cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+
+        let frequency = mem.sys_read_u16(self.frequency_address) & 0x7FF;
+        let period = (2048 - frequency as u32) * 16;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = period;
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+    pub fn clock_sweep(&mut self, mem: &mut InternalMemory) {
+        let Some(sweep_address) = self.sweep_address else { return; };
+        if !self.enabled || !self.sweep_enabled {
+            return;
+        }
+
+        let sweep = mem.sys_read_u16(sweep_address);
+        let sweep_time = (sweep >> 4) as u8 & 0x7;
+        if sweep_time == 0 {
+            return;
+        }
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+        if self.sweep_timer != 0 {
+            return;
+        }
+        self.sweep_timer = sweep_time;
+
+        let new_frequency = self.next_sweep_frequency(sweep);
+        if new_frequency > 0x7FF {
+            self.enabled = false;
+            return;
+        }
+        if sweep & 0x7 == 0 {
+            return;
+        }
+
+        // the new frequency actually gets written back into the register
+        self.shadow_frequency = new_frequency;
+        let frequency_reg = mem.sys_read_u16(self.frequency_address);
+        mem.sys_write_u16(self.frequency_address, (frequency_reg & !0x7FF) | new_frequency);
+    }
+    fn next_sweep_frequency(&self, sweep: u16) -> u16 {
+        let change = self.shadow_frequency >> (sweep & 0x7);
+        match (sweep >> 3) & 1 == 1 {
+            true => self.shadow_frequency.wrapping_sub(change),
+            false => self.shadow_frequency + change,
+        }
+    }
+
+    pub fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+        let volume = self.envelope.volume as i16;
+        match DUTY_PATTERNS[self.duty][self.duty_step] {
+            true => volume,
+            false => -volume,
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::apu::{ApuRegisters, LengthCounter};
+use crate::mem::memory::InternalMemory;
+
+pub struct WaveChannel {
+    pub enabled: bool,
+
+    timer: u32,
+    // which of the 32 (or 64 in two bank mode) samples is being played
+    position: usize,
+    sample: u8,
+    length: LengthCounter,
+    volume_shift: VolumeShift,
+}
+enum VolumeShift {
+    Mute,
+    Full,
+    Half,
+    Quarter,
+    ThreeQuarters,
+}
+impl WaveChannel {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            length: LengthCounter::default(),
+            volume_shift: VolumeShift::Mute,
+        }
+    }
+
+    pub fn check_trigger(&mut self, mem: &mut InternalMemory) {
+        let select = mem.sys_read_u16(ApuRegisters::Sound3CntL as u32);
+        let length_volume = mem.sys_read_u16(ApuRegisters::Sound3CntH as u32);
+        let frequency_reg = mem.sys_read_u16(ApuRegisters::Sound3CntX as u32);
+
+        self.volume_shift = match ((length_volume >> 15) & 1 == 1, (length_volume >> 13) & 0x3) {
+            (true, _) => VolumeShift::ThreeQuarters,
+            (false, 0) => VolumeShift::Mute,
+            (false, 1) => VolumeShift::Full,
+            (false, 2) => VolumeShift::Half,
+            (false, 3) => VolumeShift::Quarter,
+            _ => unreachable!(),
+        };
+        self.length.enabled = (frequency_reg >> 14) & 1 == 1;
+
+        // the playback bit acts like the DAC of the other channels
+        let playback = (select >> 7) & 1 == 1;
+        if !playback {
+            self.enabled = false;
+        }
+        if (frequency_reg >> 15) & 1 == 0 {
+            return;
+        }
+        mem.sys_write_u16(ApuRegisters::Sound3CntX as u32, frequency_reg & 0x7FFF);
+
+        self.enabled = playback;
+        self.length.remaining = 256 - (length_volume & 0xFF);
+        self.timer = (2048 - (frequency_reg & 0x7FF) as u32) * 8;
+        self.position = 0;
+        self.sample = read_sample(mem, select, 0);
+    }
+
+    pub fn tick(&mut self, mem: &InternalMemory, mut cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+
+        let select = mem.sys_read_u16(ApuRegisters::Sound3CntL as u32);
+        let frequency = mem.sys_read_u16(ApuRegisters::Sound3CntX as u32) & 0x7FF;
+        let period = (2048 - frequency as u32) * 8;
+
+        // one bank is 32 samples, both are 64
+        let samples = match (select >> 5) & 1 == 1 {
+            true => 64,
+            false => 32,
+        };
+        let mut advanced = false;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = period;
+            self.position = (self.position + 1) % samples;
+            advanced = true;
+        }
+        self.timer -= cycles;
+
+        if advanced {
+            self.sample = read_sample(mem, select, self.position);
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn output(&self) -> i16 {
+        if !self.enabled {
+            return 0;
+        }
+
+        // centre the 4-bit sample so it matches the square channels
+        let centred = self.sample as i16 * 2 - 15;
+        match self.volume_shift {
+            VolumeShift::Mute => 0,
+            VolumeShift::Full => centred,
+            VolumeShift::Half => centred >> 1,
+            VolumeShift::Quarter => centred >> 2,
+            VolumeShift::ThreeQuarters => (centred * 3) >> 2,
+        }
+    }
+}
+
+/// the playing bank is the one selected in SOUND3CNT_L, the high nibble
+/// of each byte is played first
+fn read_sample(mem: &InternalMemory, select: u16, position: usize) -> u8 {
+    let bank = (select >> 6) as usize & 1;
+    let absolute = (bank * 32 + position) % 64;
+    let byte = mem.wave_ram[absolute / 2];
+
+    match absolute.is_multiple_of(2) {
+        true => byte >> 4,
+        false => byte & 0xF,
+    }
+}
@@ -5,21 +5,26 @@ use super::*;
 use super::decode::DecodedArm;
 use super::get_shifted_value;
 
+/// returns how many cycles it took, which is every memory access it made plus any
+/// internal cycles. Fetching the next opcode isn't counted here
 pub fn execute_arm<M: CpuInterface>(
     opcode: u32, 
     cpu: &mut Cpu,
     memory: &mut M,
-) {
+) -> u32 {
     // println!("{:?} {:X}", assemblify::to_arm_assembly(opcode), opcode);
 
     // first check if we even have to do it
     let condition = opcode >> 28;
     if !check_condition(condition, &cpu.cpsr) {
-        return;
+        return 0;
     }
 
     use DecodedArm::*;
     let instruction = decode_arm(opcode);
+    // has to be worked out before anything changes the registers
+    let internal = internal_cycles(opcode, instruction, cpu);
+    let start = memory.access_cycles();
     match instruction {
         DataProcessing => data_processing(opcode, cpu),
         Multiply => multiply(opcode, cpu),
@@ -35,7 +40,29 @@ pub fn execute_arm<M: CpuInterface>(
         CoprocDataOperation => panic!("Coprocessor Data Operations arent handled for GBA"),
         CoprocDataTransfer => panic!("Coprocessor Data Transfers arent handled for GBA"),
         CoprocRegTransfer => panic!("Coprocessor Register Transfers arent handled for GBA"),
-        Swi => software_interrupt(cpu),
+        Swi => software_interrupt(opcode, cpu, memory),
+    }
+    memory.idle(internal);
+    return memory.access_cycles().wrapping_sub(start) + internal;
+}
+
+/// the I cycles, where the CPU is busy but not using the bus
+fn internal_cycles(opcode: u32, instruction: DecodedArm, cpu: &Cpu) -> u32 {
+    use DecodedArm::*;
+    let is_load = (opcode >> 20) & 1 == 1;
+    let rs = cpu.get_register((opcode >> 8) as u8 & 0xF);
+    let accumulate = (opcode >> 21) & 1 == 1;
+    match instruction {
+        // shifting by a register
+        DataProcessing => ((opcode >> 25) & 1 == 0 && (opcode >> 4) & 1 == 1) as u32,
+        Multiply => multiply_cycles(rs, true) + accumulate as u32,
+        MultiplyLong => {
+            let signed = (opcode >> 22) & 1 == 1;
+            multiply_cycles(rs, signed) + 1 + accumulate as u32
+        }
+        SingleDataSwap => 1,
+        HalfwordTransferReg | HalfwordTransferImm | SingleDataTransfer | BlockDataTransfer => is_load as u32,
+        _ => 0,
     }
 }
 
@@ -366,7 +393,12 @@ fn multiply_long(opcode: u32, cpu: &mut Cpu) {
     }
 }
 /// this instruction shouldnt change any of the CPSR flags
-fn software_interrupt(cpu: &mut Cpu) {
+fn software_interrupt<M: CpuInterface>(opcode: u32, cpu: &mut Cpu, memory: &mut M) {
+    // the comment field is 24 bits but the BIOS only looks at the top 8
+    if hle::intercept_swi((opcode >> 16) as u8, cpu, memory) {
+        return;
+    }
+
     // spsr_svc gets the old cpsr transferred into it
     cpu.set_specific_spsr(cpu.cpsr, ProcessorMode::Supervisor);
 
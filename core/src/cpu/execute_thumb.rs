@@ -4,15 +4,19 @@ use crate::mem::bus::CpuInterface;
 
 use super::get_shifted_value;
 
+/// the same as `execute_arm`, it returns the cycles taken without the next fetch
 pub fn execute_thumb<M: CpuInterface>(
     opcode: u16,
     cpu: &mut Cpu,
     memory: &mut M,
-) {
+) -> u32 {
     // println!("{:?}", assemblify::to_thumb_assembly(opcode));
 
     use DecodedThumb::*;
     let instruction = decode_thumb(opcode);
+    // has to be worked out before anything changes the registers
+    let internal = internal_cycles(opcode, instruction, cpu);
+    let start = memory.access_cycles();
     match instruction {
         MoveShifted => move_shifted(opcode, cpu),
         AddSub => add_sub(opcode, cpu),
@@ -30,10 +34,32 @@ pub fn execute_thumb<M: CpuInterface>(
         PushPop => push_pop(opcode, cpu, memory),
         MemMultiple => mem_multiple(opcode, cpu, memory),
         CondBranch => conditional_branch(opcode, cpu),
-        Swi => software_interrupt(cpu),
+        Swi => software_interrupt(opcode, cpu, memory),
         UncondBranch => unconditional_branch(opcode, cpu),
         LongBranch => long_branch_link(opcode, cpu),
     }
+    memory.idle(internal);
+    return memory.access_cycles().wrapping_sub(start) + internal;
+}
+
+/// the I cycles, where the CPU is busy but not using the bus
+fn internal_cycles(opcode: u16, instruction: DecodedThumb, cpu: &Cpu) -> u32 {
+    use DecodedThumb::*;
+    let is_load = (opcode >> 11) & 1 == 1;
+    match instruction {
+        AluOperation => match (opcode >> 6) & 0xF {
+            // shifting by a register
+            0x2 | 0x3 | 0x4 | 0x7 => 1,
+            // mul, rd is what the ARM version would have in rs
+            0xD => multiply_cycles(cpu.get_register(opcode as u8 & 0x7), true),
+            _ => 0,
+        }
+        PcRelativeLoad => 1,
+        // strh is the only one of these which isn't a load
+        MemSignExtended => ((opcode >> 10) & 0b11 != 0) as u32,
+        MemRegOffset | MemImmOffset | MemHalfword | MemSpRelative | PushPop | MemMultiple => is_load as u32,
+        _ => 0,
+    }
 }
 
 fn move_shifted(opcode: u16, cpu: &mut Cpu) {
@@ -659,7 +685,11 @@ fn long_branch_link(opcode: u16, cpu: &mut Cpu) {
     };
 }
 
-fn software_interrupt(cpu: &mut Cpu) {
+fn software_interrupt<M: CpuInterface>(opcode: u16, cpu: &mut Cpu, memory: &mut M) {
+    if hle::intercept_swi(opcode as u8, cpu, memory) {
+        return;
+    }
+
     cpu.set_specific_spsr(cpu.cpsr, ProcessorMode::Supervisor);
     cpu.cpsr.mode = ProcessorMode::Supervisor;
     cpu.cpsr.i = true;
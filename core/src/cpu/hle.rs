@@ -0,0 +1,710 @@
+use std::f32::consts::PI;
+use crate::cpu::{Cpu, CpuMemoryRegisters, ProcessorMode};
+use crate::mem::bus::CpuInterface;
+
+// what GetBiosChecksum gives on a GBA (a DS has a different BIOS)
+const BIOS_CHECKSUM: u32 = 0xBAAE187F;
+// set by the game's interrupt handler, IntrWait checks these rather than IF
+const BIOS_INTERRUPT_FLAGS: u32 = 0x3007FF8;
+const HALTCNT: u32 = 0x4000301;
+
+/// the BIOS image used instead of the real one, SWIs never reach it so all it needs is
+/// the reset and interrupt vectors and the interrupt handler at the same place as the
+/// real BIOS has it (anything reading BIOS open bus sees the same opcodes)
+pub static HLE_BIOS: [u8; 0x4000] = hle_bios();
+const fn hle_bios() -> [u8; 0x4000] {
+    let code: [(usize, u32); 9] = [
+        (0x000, 0xE3A0F302), // mov pc, #0x8000000
+        (0x008, 0xE1B0F00E), // movs pc, lr
+        (0x018, 0xEA000042), // b 0x128
+        (0x128, 0xE92D500F), // stmfd sp!, {r0-r3, r12, lr}
+        (0x12C, 0xE3A00301), // mov r0, #0x4000000
+        (0x130, 0xE28FE000), // add lr, pc, #0
+        (0x134, 0xE510F004), // ldr pc, [r0, #-4]
+        (0x138, 0xE8BD500F), // ldmfd sp!, {r0-r3, r12, lr}
+        (0x13C, 0xE25EF004), // subs pc, lr, #4
+    ];
+
+    let mut bios = [0; 0x4000];
+    let mut i = 0;
+    while i < code.len() {
+        let (address, opcode) = code[i];
+        bios[address] = opcode as u8;
+        bios[address + 1] = (opcode >> 8) as u8;
+        bios[address + 2] = (opcode >> 16) as u8;
+        bios[address + 3] = (opcode >> 24) as u8;
+        i += 1;
+    }
+    return bios;
+}
+
+pub fn swi_name(comment: u8) -> &'static str {
+    match comment {
+        0x00 => "SoftReset",
+        0x01 => "RegisterRamReset",
+        0x02 => "Halt",
+        0x03 => "Stop",
+        0x04 => "IntrWait",
+        0x05 => "VBlankIntrWait",
+        0x06 => "Div",
+        0x07 => "DivArm",
+        0x08 => "Sqrt",
+        0x09 => "ArcTan",
+        0x0A => "ArcTan2",
+        0x0B => "CpuSet",
+        0x0C => "CpuFastSet",
+        0x0D => "GetBiosChecksum",
+        0x0E => "BgAffineSet",
+        0x0F => "ObjAffineSet",
+        0x10 => "BitUnPack",
+        0x11 => "LZ77UnCompWram",
+        0x12 => "LZ77UnCompVram",
+        0x13 => "HuffUnComp",
+        0x14 => "RLUnCompWram",
+        0x15 => "RLUnCompVram",
+        0x16 => "Diff8bitUnFilterWram",
+        0x17 => "Diff8bitUnFilterVram",
+        0x18 => "Diff16bitUnFilter",
+        0x19 => "SoundBias",
+        0x1A => "SoundDriverInit",
+        0x1B => "SoundDriverMode",
+        0x1C => "SoundDriverMain",
+        0x1D => "SoundDriverVSync",
+        0x1E => "SoundChannelClear",
+        0x1F => "MidiKey2Freq",
+        0x25 => "MultiBoot",
+        0x26 => "HardReset",
+        0x28 => "SoundDriverVSyncOff",
+        0x29 => "SoundDriverVSyncOn",
+        _ => "Unknown",
+    }
+}
+
+/// called for every SWI before the CPU jumps to the BIOS, returning true if it has
+/// already been dealt with and the jump shouldn't happen
+pub fn intercept_swi<M: CpuInterface>(comment: u8, cpu: &mut Cpu, memory: &mut M) -> bool {
+    if let Some(symbols) = &cpu.swi_trace {
+        eprintln!(
+            "SWI {comment:02X} {} r0={:08X} r1={:08X} r2={:08X} r3={:08X} at {}",
+            swi_name(comment),
+            cpu.get_register(0),
+            cpu.get_register(1),
+            cpu.get_register(2),
+            cpu.get_register(3),
+            symbols.describe(swi_address(cpu)),
+        );
+    }
+    if !cpu.hle_bios {
+        return false;
+    }
+    hle_swi(comment, cpu, memory);
+    return true;
+}
+
+/// runs the SWI natively instead of jumping into the BIOS. The registers are
+/// left the same as the real thing would leave them (as far as games care)
+pub fn hle_swi<M: CpuInterface>(comment: u8, cpu: &mut Cpu, memory: &mut M) {
+    let r0 = cpu.get_register(0);
+    let r1 = cpu.get_register(1);
+    let r2 = cpu.get_register(2);
+    let r3 = cpu.get_register(3);
+
+    match comment {
+        0x00 => soft_reset(cpu, memory),
+        0x01 => register_ram_reset(r0, memory),
+        0x02 => memory.write_u8(HALTCNT, 0),
+        // stopping is only undone by the keypad or link cable, halting is close enough
+        0x03 => memory.write_u8(HALTCNT, 0x80),
+        0x04 => intr_wait(cpu, memory, r0 != 0, r1 as u16),
+        0x05 => intr_wait(cpu, memory, true, 1),
+        0x06 => div(cpu, r0 as i32, r1 as i32),
+        0x07 => div(cpu, r1 as i32, r0 as i32),
+        0x08 => set_registers(cpu, &[(0, r0.isqrt())]),
+        0x09 => {
+            let (result, a, b) = arc_tan(r0 as i16 as i32);
+            set_registers(cpu, &[(0, result as u32), (1, a as u32), (3, b as u32)]);
+        }
+        0x0A => {
+            let result = arc_tan2(r0 as i16 as i32, r1 as i16 as i32);
+            set_registers(cpu, &[(0, result as u32)]);
+        }
+        0x0B => cpu_set(r0, r1, r2, memory),
+        0x0C => cpu_fast_set(r0, r1, r2, memory),
+        0x0D => set_registers(cpu, &[(0, BIOS_CHECKSUM)]),
+        0x0E => bg_affine_set(r0, r1, r2, memory),
+        0x0F => obj_affine_set(r0, r1, r2, r3, memory),
+        0x10 => bit_unpack(r0, r1, r2, memory),
+        0x11 => write_bytes(r1, &lz77(r0, memory), memory),
+        0x12 => write_halfwords(r1, &lz77(r0, memory), memory),
+        0x13 => huffman(r0, r1, memory),
+        0x14 => write_bytes(r1, &run_length(r0, memory), memory),
+        0x15 => write_halfwords(r1, &run_length(r0, memory), memory),
+        0x16 => write_bytes(r1, &diff_8bit(r0, memory), memory),
+        0x17 => write_halfwords(r1, &diff_8bit(r0, memory), memory),
+        0x18 => diff_16bit(r0, r1, memory),
+        _ => if cpu.swi_trace.is_some() {
+            eprintln!("SWI {comment:X} ({}) isn't emulated", swi_name(comment));
+        },
+    }
+}
+
+fn set_registers(cpu: &mut Cpu, values: &[(u8, u32)]) {
+    for (register, value) in values {
+        *cpu.get_register_mut(*register) = *value;
+    }
+}
+
+/// the address of the SWI itself, for anything which needs to run it again
+fn swi_address(cpu: &Cpu) -> u32 {
+    match cpu.cpsr.t {
+        true => cpu.get_register(15) - 4,
+        false => cpu.get_register(15) - 8,
+    }
+}
+
+fn soft_reset<M: CpuInterface>(cpu: &mut Cpu, memory: &mut M) {
+    // multiboot games set this so they restart from EWRAM instead of the cart
+    let return_to_ram = memory.read_u8(0x3007FFA) != 0;
+    for address in (0x3007E00..0x3008000).step_by(4) {
+        memory.write_u32(address, 0);
+    }
+
+    *cpu = Cpu {
+        hle_bios: cpu.hle_bios,
+        swi_trace: cpu.swi_trace.take(),
+        ..Cpu::new()
+    };
+    cpu.pc = match return_to_ram {
+        true => 0x2000000,
+        false => 0x8000000,
+    };
+    cpu.clear_pipeline();
+}
+
+fn fill<M: CpuInterface>(memory: &mut M, range: std::ops::Range<u32>) {
+    for address in range.step_by(4) {
+        memory.write_u32(address, 0);
+    }
+}
+fn register_ram_reset<M: CpuInterface>(flags: u32, memory: &mut M) {
+    // the screen is always forced blank afterwards
+    memory.write_u16(0x4000000, 0x80);
+
+    if flags & 0x01 != 0 { fill(memory, 0x2000000..0x2040000); }
+    // the top 0x200 bytes has the stacks and interrupt handler in it
+    if flags & 0x02 != 0 { fill(memory, 0x3000000..0x3007E00); }
+    if flags & 0x04 != 0 { fill(memory, 0x5000000..0x5000400); }
+    if flags & 0x08 != 0 { fill(memory, 0x6000000..0x6018000); }
+    if flags & 0x10 != 0 { fill(memory, 0x7000000..0x7000400); }
+    if flags & 0x20 != 0 {
+        fill(memory, 0x4000120..0x4000130);
+        memory.write_u16(0x4000134, 0x8000);
+        fill(memory, 0x4000140..0x4000160);
+    }
+    if flags & 0x40 != 0 {
+        fill(memory, 0x4000060..0x4000080);
+        fill(memory, 0x4000084..0x4000088);
+        memory.write_u16(0x4000088, 0x200);
+        fill(memory, 0x4000090..0x40000A8);
+    }
+    if flags & 0x80 != 0 {
+        for address in (0x4000004..0x4000060).step_by(2) {
+            memory.write_u16(address, 0);
+        }
+        // the rotation scaling parameters go back to the identity
+        for base in [0x4000020, 0x4000030] {
+            memory.write_u16(base, 0x100);
+            memory.write_u16(base + 6, 0x100);
+        }
+        fill(memory, 0x40000B0..0x4000110);
+        fill(memory, 0x4000200..0x400020C);
+    }
+}
+
+/// `IntrWait` halts until one of `flags` has been set by the game's interrupt handler.
+/// Waking up goes through the normal interrupt path, so the SWI is simply run again
+/// afterwards until the flags show up
+fn intr_wait<M: CpuInterface>(cpu: &mut Cpu, memory: &mut M, discard: bool, flags: u16) {
+    memory.write_u16(CpuMemoryRegisters::Ime as u32, 1);
+    let bios_flags = memory.read_u16(BIOS_INTERRUPT_FLAGS);
+    // only the first time round discards, otherwise VBlankIntrWait would never return
+    if discard && cpu.intr_waiting.is_none() {
+        memory.write_u16(BIOS_INTERRUPT_FLAGS, bios_flags & !flags);
+    } else if bios_flags & flags != 0 {
+        memory.write_u16(BIOS_INTERRUPT_FLAGS, bios_flags & !flags);
+        if let Some(cpsr) = cpu.intr_waiting.take() {
+            cpu.cpsr = cpsr;
+        }
+        return;
+    }
+
+    // the BIOS waits in System mode with IRQs on, whatever the caller had them as
+    if cpu.intr_waiting.is_none() {
+        cpu.intr_waiting = Some(cpu.cpsr);
+    }
+    cpu.cpsr.mode = ProcessorMode::System;
+    cpu.cpsr.i = false;
+    let swi = swi_address(cpu);
+    *cpu.get_register_mut(15) = swi;
+    cpu.clear_pipeline();
+    memory.write_u8(HALTCNT, 0);
+}
+
+fn div(cpu: &mut Cpu, numerator: i32, denominator: i32) {
+    // the real BIOS gets stuck forever, this is what it would be heading towards
+    if denominator == 0 {
+        let sign = match numerator < 0 {
+            true => -1i32,
+            false => 1,
+        };
+        set_registers(cpu, &[(0, sign as u32), (1, numerator as u32), (3, 1)]);
+        return;
+    }
+    let quotient = numerator.wrapping_div(denominator);
+    let remainder = numerator.wrapping_rem(denominator);
+    set_registers(cpu, &[(0, quotient as u32), (1, remainder as u32), (3, quotient.unsigned_abs())]);
+}
+
+/// the BIOS's polynomial, `tan` is 1.14 fixed point and the result is -0x4000..0x4000 for -pi/2..pi/2
+fn arc_tan(tan: i32) -> (i32, i32, i32) {
+    // the BIOS just lets these wrap for big inputs
+    let a = -(tan.wrapping_mul(tan) >> 14);
+    let mut b = (0xA9i32.wrapping_mul(a) >> 14) + 0x390;
+    for constant in [0x91C, 0xFB6, 0x16AA, 0x2081, 0x3651, 0xA2F9] {
+        b = (b.wrapping_mul(a) >> 14) + constant;
+    }
+    return (tan.wrapping_mul(b) >> 16, a, b);
+}
+/// the full circle version, 0..0xFFFF going anticlockwise from the positive x axis
+fn arc_tan2(x: i32, y: i32) -> u16 {
+    if y == 0 {
+        return match x >= 0 {
+            true => 0,
+            false => 0x8000,
+        };
+    }
+    if x == 0 {
+        return match y >= 0 {
+            true => 0x4000,
+            false => 0xC000,
+        };
+    }
+
+    let result = if y >= 0 {
+        if x >= 0 && x >= y {
+            arc_tan((y << 14) / x).0
+        } else if x < 0 && -x >= y {
+            arc_tan((y << 14) / x).0 + 0x8000
+        } else {
+            0x4000 - arc_tan((x << 14) / y).0
+        }
+    } else if x <= 0 && -x > -y {
+        arc_tan((y << 14) / x).0 + 0x8000
+    } else if x > 0 && x >= -y {
+        arc_tan((y << 14) / x).0 + 0x10000
+    } else {
+        0xC000 - arc_tan((x << 14) / y).0
+    };
+    return result as u16;
+}
+
+/// r2 is the count in bits 0..20, bit 24 to fill rather than copy and bit 26 for words
+fn cpu_set<M: CpuInterface>(source: u32, destination: u32, control: u32, memory: &mut M) {
+    let count = control & 0x1FFFFF;
+    let fill = (control >> 24) & 1 == 1;
+    let is_32_bit = (control >> 26) & 1 == 1;
+
+    let size = match is_32_bit {
+        true => 4,
+        false => 2,
+    };
+    let source = source & !(size - 1);
+    let destination = destination & !(size - 1);
+    for i in 0..count {
+        let from = match fill {
+            true => source,
+            false => source.wrapping_add(i * size),
+        };
+        let to = destination.wrapping_add(i * size);
+        match is_32_bit {
+            true => {
+                let data = memory.read_u32_unrotated(from);
+                memory.write_u32(to, data);
+            }
+            false => {
+                let data = memory.read_u16(from);
+                memory.write_u16(to, data);
+            }
+        }
+    }
+}
+/// always words, and always a multiple of 8 of them
+fn cpu_fast_set<M: CpuInterface>(source: u32, destination: u32, control: u32, memory: &mut M) {
+    let count = ((control & 0x1FFFFF) + 7) & !7;
+    let fill = (control >> 24) & 1 == 1;
+    cpu_set(source, destination, count | ((fill as u32) << 24) | (1 << 26), memory);
+}
+
+fn write_affine<M: CpuInterface>(memory: &mut M, address: u32, value: f32) {
+    memory.write_u16(address, (value * 256.) as i32 as u16);
+}
+// only the top 8 bits of the angle get used
+fn affine_angle(angle: u16) -> f32 {
+    (angle >> 8) as f32 / 128. * PI
+}
+
+/// 20 byte sources (centre x and y, screen x and y, scale x and y, angle)
+/// into the BG2/BG3 parameters and reference point
+fn bg_affine_set<M: CpuInterface>(source: u32, destination: u32, count: u32, memory: &mut M) {
+    for i in 0..count {
+        let source = source.wrapping_add(i.wrapping_mul(20));
+        let destination = destination.wrapping_add(i.wrapping_mul(16));
+        let centre_x = memory.read_u32_unrotated(source) as i32 as f32 / 256.;
+        let centre_y = memory.read_u32_unrotated(source.wrapping_add(4)) as i32 as f32 / 256.;
+        let screen_x = memory.read_u16(source.wrapping_add(8)) as i16 as f32;
+        let screen_y = memory.read_u16(source.wrapping_add(10)) as i16 as f32;
+        let scale_x = memory.read_u16(source.wrapping_add(12)) as i16 as f32 / 256.;
+        let scale_y = memory.read_u16(source.wrapping_add(14)) as i16 as f32 / 256.;
+        let angle = affine_angle(memory.read_u16(source.wrapping_add(16)));
+
+        let (sin, cos) = angle.sin_cos();
+        let (pa, pb, pc, pd) = (cos * scale_x, -sin * scale_x, sin * scale_y, cos * scale_y);
+        let start_x = centre_x - (pa * screen_x + pb * screen_y);
+        let start_y = centre_y - (pc * screen_x + pd * screen_y);
+
+        write_affine(memory, destination, pa);
+        write_affine(memory, destination.wrapping_add(2), pb);
+        write_affine(memory, destination.wrapping_add(4), pc);
+        write_affine(memory, destination.wrapping_add(6), pd);
+        memory.write_u32(destination.wrapping_add(8), (start_x * 256.) as i32 as u32);
+        memory.write_u32(destination.wrapping_add(12), (start_y * 256.) as i32 as u32);
+    }
+}
+/// 8 byte sources (scale x and y, angle) into 4 parameters `stride` bytes apart,
+/// which is 2 for a plain array or 8 to go straight into OAM
+fn obj_affine_set<M: CpuInterface>(source: u32, destination: u32, count: u32, stride: u32, memory: &mut M) {
+    for i in 0..count {
+        let source = source.wrapping_add(i.wrapping_mul(8));
+        let destination = destination.wrapping_add(i.wrapping_mul(stride).wrapping_mul(4));
+        let scale_x = memory.read_u16(source) as i16 as f32 / 256.;
+        let scale_y = memory.read_u16(source.wrapping_add(2)) as i16 as f32 / 256.;
+        let angle = affine_angle(memory.read_u16(source.wrapping_add(4)));
+
+        let (sin, cos) = angle.sin_cos();
+        write_affine(memory, destination, cos * scale_x);
+        write_affine(memory, destination.wrapping_add(stride), -sin * scale_x);
+        write_affine(memory, destination.wrapping_add(stride.wrapping_mul(2)), sin * scale_y);
+        write_affine(memory, destination.wrapping_add(stride.wrapping_mul(3)), cos * scale_y);
+    }
+}
+
+/// widens every `source_width` bit unit into `destination_width` bits, adding the offset
+/// to anything that isn't 0 (or everything, if bit 31 of it is set)
+fn bit_unpack<M: CpuInterface>(source: u32, destination: u32, info: u32, memory: &mut M) {
+    let length = memory.read_u16(info) as u32;
+    let source_width = memory.read_u8(info.wrapping_add(2)) as u32;
+    let destination_width = memory.read_u8(info.wrapping_add(3)) as u32;
+    let data = memory.read_u32_unrotated(info.wrapping_add(4));
+    let offset = data & 0x7FFFFFFF;
+    let offset_zero = (data >> 31) & 1 == 1;
+    // the BIOS doesn't do anything sensible with other widths
+    if ![1, 2, 4, 8].contains(&source_width) || ![1, 2, 4, 8, 16, 32].contains(&destination_width) {
+        return;
+    }
+
+    let mut output = 0u32;
+    let mut output_bits = 0;
+    let mut destination = destination & !3;
+    for i in 0..length {
+        let byte = memory.read_u8(source.wrapping_add(i)) as u32;
+        for shift in (0..8).step_by(source_width as usize) {
+            let mut unit = (byte >> shift) & ((1 << source_width) - 1);
+            if unit != 0 || offset_zero {
+                unit = unit.wrapping_add(offset);
+            }
+            if destination_width < 32 {
+                unit &= (1 << destination_width) - 1;
+            }
+            output |= unit << output_bits;
+            output_bits += destination_width;
+            if output_bits == 32 {
+                memory.write_u32(destination, output);
+                destination = destination.wrapping_add(4);
+                output = 0;
+                output_bits = 0;
+            }
+        }
+    }
+}
+
+/// every compressed format starts with the type in bits 4..8 and the size after
+fn decompressed_size<M: CpuInterface>(source: u32, memory: &mut M) -> usize {
+    (memory.read_u32_unrotated(source) >> 8) as usize
+}
+fn write_bytes<M: CpuInterface>(destination: u32, data: &[u8], memory: &mut M) {
+    for (i, byte) in data.iter().enumerate() {
+        memory.write_u8(destination.wrapping_add(i as u32), *byte);
+    }
+}
+/// VRAM can't take single bytes, so the Vram versions of everything write halfwords
+fn write_halfwords<M: CpuInterface>(destination: u32, data: &[u8], memory: &mut M) {
+    for (i, pair) in data.chunks(2).enumerate() {
+        let high = pair.get(1).copied().unwrap_or(0);
+        memory.write_u16(destination.wrapping_add(i as u32 * 2), (pair[0] as u16) | ((high as u16) << 8));
+    }
+}
+
+/// a flag byte says whether each of the next 8 blocks is a literal byte or
+/// a 2 byte (length, distance back) copy of what has already been written
+fn lz77<M: CpuInterface>(source: u32, memory: &mut M) -> Vec<u8> {
+    let size = decompressed_size(source, memory);
+    let mut output = Vec::with_capacity(size);
+    let mut source = source.wrapping_add(4);
+
+    while output.len() < size {
+        let flags = memory.read_u8(source);
+        source = source.wrapping_add(1);
+        for bit in (0..8).rev() {
+            if output.len() >= size {
+                break;
+            }
+            if (flags >> bit) & 1 == 0 {
+                output.push(memory.read_u8(source));
+                source = source.wrapping_add(1);
+                continue;
+            }
+
+            let first = memory.read_u8(source) as usize;
+            let second = memory.read_u8(source.wrapping_add(1)) as usize;
+            source = source.wrapping_add(2);
+            let length = (first >> 4) + 3;
+            let distance = (((first & 0xF) << 8) | second) + 1;
+            for _ in 0..length {
+                let byte = match output.len().checked_sub(distance) {
+                    Some(index) => output[index],
+                    None => 0,
+                };
+                output.push(byte);
+            }
+        }
+    }
+    output.truncate(size);
+    return output;
+}
+
+/// a flag byte is either a run of the next byte (bit 7 set, 3..130 long)
+/// or a number of bytes to copy as they are (1..128)
+fn run_length<M: CpuInterface>(source: u32, memory: &mut M) -> Vec<u8> {
+    let size = decompressed_size(source, memory);
+    let mut output = Vec::with_capacity(size);
+    let mut source = source.wrapping_add(4);
+
+    while output.len() < size {
+        let flag = memory.read_u8(source) as usize;
+        source = source.wrapping_add(1);
+        match (flag >> 7) & 1 == 1 {
+            true => {
+                let byte = memory.read_u8(source);
+                source = source.wrapping_add(1);
+                output.extend(std::iter::repeat_n(byte, (flag & 0x7F) + 3));
+            }
+            false => {
+                for _ in 0..(flag & 0x7F) + 1 {
+                    output.push(memory.read_u8(source));
+                    source = source.wrapping_add(1);
+                }
+            }
+        }
+    }
+    output.truncate(size);
+    return output;
+}
+
+fn diff_8bit<M: CpuInterface>(source: u32, memory: &mut M) -> Vec<u8> {
+    let size = decompressed_size(source, memory);
+    let mut output = Vec::with_capacity(size);
+    let mut previous = 0u8;
+    for i in 0..size as u32 {
+        previous = previous.wrapping_add(memory.read_u8(source.wrapping_add(4 + i)));
+        output.push(previous);
+    }
+    return output;
+}
+fn diff_16bit<M: CpuInterface>(source: u32, destination: u32, memory: &mut M) {
+    let size = decompressed_size(source, memory) as u32;
+    let mut previous = 0u16;
+    for i in (0..size).step_by(2) {
+        previous = previous.wrapping_add(memory.read_u16(source.wrapping_add(4 + i)));
+        memory.write_u16(destination.wrapping_add(i), previous);
+    }
+}
+
+/// the tree comes first, each node has the offset to its children in bits 0..6
+/// and whether the left (bit 7) or right (bit 6) child is data. The bitstream
+/// after it is read a word at a time from the top bit down
+fn huffman<M: CpuInterface>(source: u32, destination: u32, memory: &mut M) {
+    let header = memory.read_u32_unrotated(source);
+    let size = header >> 8;
+    let data_bits = header & 0xF;
+    if data_bits != 4 && data_bits != 8 {
+        return;
+    }
+
+    let tree = source.wrapping_add(4);
+    let root = tree.wrapping_add(1);
+    let mut stream = tree.wrapping_add((memory.read_u8(tree) as u32 + 1) * 2);
+    let mut node_address = root;
+    let mut node = memory.read_u8(node_address);
+
+    let mut destination = destination & !3;
+    let mut output = 0u32;
+    let mut output_bits = 0;
+    let mut written = 0;
+    while written < size {
+        let word = memory.read_u32_unrotated(stream);
+        stream = stream.wrapping_add(4);
+        for bit in (0..32).rev() {
+            let direction = (word >> bit) & 1;
+            let child = (node_address & !1).wrapping_add((node as u32 & 0x3F) * 2 + 2 + direction);
+            let is_data = node & (0x80 >> direction) != 0;
+            if !is_data {
+                node_address = child;
+                node = memory.read_u8(child);
+                continue;
+            }
+
+            let value = memory.read_u8(child) as u32 & ((1 << data_bits) - 1);
+            output |= value << output_bits;
+            output_bits += data_bits;
+            node_address = root;
+            node = memory.read_u8(root);
+            if output_bits == 32 {
+                memory.write_u32(destination, output);
+                destination = destination.wrapping_add(4);
+                written += 4;
+                output = 0;
+                output_bits = 0;
+                if written >= size {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: u32 = 0x000;
+    const INFO: u32 = 0x700;
+    const DESTINATION: u32 = 0x800;
+
+    /// just enough flat memory for the decompressors to run against
+    struct TestMemory(Vec<u8>);
+    impl TestMemory {
+        fn new(source: &[u8]) -> Self {
+            let mut memory = vec![0; 0x1000];
+            memory[..source.len()].copy_from_slice(source);
+            return Self(memory);
+        }
+        fn slice(&self, address: u32, length: usize) -> &[u8] {
+            &self.0[address as usize..address as usize + length]
+        }
+    }
+    impl CpuInterface for TestMemory {
+        fn read_u8(&mut self, address: u32) -> u8 {
+            self.0[address as usize]
+        }
+        fn read_u16(&mut self, address: u32) -> u16 {
+            u16::from_le_bytes(self.slice(address, 2).try_into().unwrap())
+        }
+        fn read_u32_unrotated(&mut self, address: u32) -> u32 {
+            u32::from_le_bytes(self.slice(address, 4).try_into().unwrap())
+        }
+        fn read_u32_rotated(&mut self, address: u32) -> u32 {
+            self.read_u32_unrotated(address)
+        }
+
+        fn write_u8(&mut self, address: u32, data: u8) {
+            self.0[address as usize] = data;
+        }
+        fn write_u16(&mut self, address: u32, data: u16) {
+            self.0[address as usize..address as usize + 2].copy_from_slice(&data.to_le_bytes());
+        }
+        fn write_u32(&mut self, address: u32, data: u32) {
+            self.0[address as usize..address as usize + 4].copy_from_slice(&data.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn lz77_literals_and_copies() {
+        // A, B, C, then 6 bytes from 3 back, then X
+        let mut memory = TestMemory::new(&[0x10, 10, 0, 0, 0x10, b'A', b'B', b'C', 0x30, 0x02, b'X']);
+        assert_eq!(lz77(SOURCE, &mut memory), b"ABCABCABCX");
+    }
+
+    #[test]
+    fn lz77_stops_at_the_size() {
+        // the copy runs past the end
+        let mut memory = TestMemory::new(&[0x10, 4, 0, 0, 0x40, b'A', 0xF0, 0x00]);
+        assert_eq!(lz77(SOURCE, &mut memory), b"AAAA");
+    }
+
+    #[test]
+    fn run_length_runs_and_raw_bytes() {
+        let mut memory = TestMemory::new(&[0x30, 7, 0, 0, 0x01, 1, 2, 0x82, 9]);
+        assert_eq!(run_length(SOURCE, &mut memory), [1, 2, 9, 9, 9, 9, 9]);
+    }
+
+    #[test]
+    fn diff_filters() {
+        let mut memory = TestMemory::new(&[0x81, 4, 0, 0, 1, 1, 0xFF, 5]);
+        assert_eq!(diff_8bit(SOURCE, &mut memory), [1, 2, 1, 6]);
+
+        let mut memory = TestMemory::new(&[0x82, 4, 0, 0, 0x00, 0x01, 0xFF, 0xFF]);
+        diff_16bit(SOURCE, DESTINATION, &mut memory);
+        assert_eq!(memory.slice(DESTINATION, 4), [0x00, 0x01, 0xFF, 0x00]);
+    }
+
+    #[test]
+    fn huffman_8bit() {
+        // the root's children are both data, 0 is 'a' and 1 is 'b'
+        let mut memory = TestMemory::new(&[
+            0x28, 4, 0, 0,
+            1, 0xC0, b'a', b'b',
+            0x00, 0x00, 0x00, 0x60,
+        ]);
+        huffman(SOURCE, DESTINATION, &mut memory);
+        assert_eq!(memory.slice(DESTINATION, 4), b"abba");
+    }
+
+    #[test]
+    fn huffman_4bit() {
+        // 0 is 0xF (masked down from 0x1F), 10 is 3 and 11 is 5
+        let mut memory = TestMemory::new(&[
+            0x24, 4, 0, 0,
+            3, 0x80, 0x1F, 0xC0, 0x03, 0x05, 0, 0,
+            0x00, 0x00, 0x00, 0x58,
+        ]);
+        huffman(SOURCE, DESTINATION, &mut memory);
+        assert_eq!(memory.read_u32_unrotated(DESTINATION), 0xFFFFF53F);
+    }
+
+    #[test]
+    fn bit_unpack_offsets() {
+        // 1 bit units into 4 bits, adding 1 to the ones that aren't 0
+        let mut memory = TestMemory::new(&[0b0000_0101, 0xF0]);
+        memory.write_u32(INFO, 0x04010002);
+        memory.write_u32(INFO + 4, 1);
+        bit_unpack(SOURCE, DESTINATION, INFO, &mut memory);
+        assert_eq!(memory.read_u32_unrotated(DESTINATION), 0x00000202);
+        assert_eq!(memory.read_u32_unrotated(DESTINATION + 4), 0x22220000);
+
+        // and to all of them
+        memory.write_u32(INFO + 4, 0x80000001);
+        bit_unpack(SOURCE, DESTINATION, INFO, &mut memory);
+        assert_eq!(memory.read_u32_unrotated(DESTINATION), 0x11111212);
+        assert_eq!(memory.read_u32_unrotated(DESTINATION + 4), 0x22221111);
+    }
+}
@@ -2,8 +2,10 @@ pub mod execute_arm;
 pub mod execute_thumb;
 pub mod decode;
 pub mod assemblify;
+pub mod hle;
 
-use crate::{mem::bus::CpuInterface, Bus};
+use crate::Bus;
+use crate::elf::SymbolTable;
 /// several different instructions make use of this behaviour
 /// I'm not sure if they all function the same but I have no reason to believe otherwise
 /// both the shifted value and the carry flag are returned
@@ -205,20 +207,34 @@ pub struct Cpu {
     pub spsr: [Cpsr; 5],
 
     pub barrel_shifter: bool,
+
+    // SWIs are run by `hle::hle_swi` instead of the BIOS
+    pub hle_bios: bool,
+    // prints every SWI with its arguments, and the symbol it was called from
+    pub swi_trace: Option<SymbolTable>,
+    // an HLE IntrWait is being run again after an interrupt woke the CPU up,
+    // this is the CPSR it was called with to go back to once it's done
+    pub intr_waiting: Option<Cpsr>,
 }
 impl Cpu {
+    /// how the BIOS leaves the CPU when it jumps to the cart, it goes through the same code
+    /// as SoftReset so r0-r12 and the link registers are all 0, and it's in system mode
+    /// with interrupts enabled
     pub fn new() -> Self {
         Self {
             unbanked_registers: [0, 0, 0, 0, 0, 0, 0, 0],
             double_banked_registers: [[0, 0], [0, 0], [0, 0], [0, 0], [0, 0]],
             many_banked_registers: [[0x03007F00, 0, 0x03007FE0, 0, 0x03007FA0, 0], [0, 0, 0, 0, 0, 0]],
             pc: 0x8000000,
-            cpsr: Cpsr::default(),
+            cpsr: Cpsr { mode: ProcessorMode::System, ..Cpsr::default() },
             spsr: [Cpsr::default(), Cpsr::default(), Cpsr::default(), Cpsr::default(), Cpsr::default()],
             barrel_shifter: false,
 
             fde: Fde::new(),
             halted: false,
+            hle_bios: false,
+            swi_trace: None,
+            intr_waiting: None,
         }
     }
     pub fn from_bios() -> Self {
@@ -233,6 +249,9 @@ impl Cpu {
 
             fde: Fde::new(),
             halted: false,
+            hle_bios: false,
+            swi_trace: None,
+            intr_waiting: None,
         }
     }
 
@@ -406,12 +425,13 @@ pub fn handle_interrupts(memory: &mut Bus, cpu: &mut Cpu) {
         return;
     }
 
-    let interrupt_allowed = memory.read_u32_unrotated(CpuMemoryRegisters::Ime as u32) & 1 == 1;
+    // these aren't real accesses so they shouldn't take any time
+    let interrupt_allowed = memory.mem.sys_read_u32(CpuMemoryRegisters::Ime as u32) & 1 == 1;
     if !interrupt_allowed && !cpu.halted {
         return;
     }
-    let interrupts_enabled = memory.read_u16(CpuMemoryRegisters::Ie as u32);
-    let interrupts_called = memory.read_u16(CpuMemoryRegisters::If as u32);
+    let interrupts_enabled = memory.mem.sys_read_u16(CpuMemoryRegisters::Ie as u32);
+    let interrupts_called = memory.mem.sys_read_u16(CpuMemoryRegisters::If as u32);
     let called_interrupts = interrupts_enabled & interrupts_called;
     if called_interrupts == 0 {
         return;
@@ -438,6 +458,18 @@ pub fn handle_interrupts(memory: &mut Bus, cpu: &mut Cpu) {
     cpu.clear_pipeline();
 }
 
+/// the internal cycles a multiply takes, it stops early once the rest of rs is all
+/// 0s (or all 1s, for anything but the unsigned long multiplies)
+pub fn multiply_cycles(rs: u32, signed: bool) -> u32 {
+    for (cycles, bits) in [(1, 8), (2, 16), (3, 24)] {
+        let top = rs >> bits;
+        if top == 0 || (signed && top == u32::MAX >> bits) {
+            return cycles;
+        }
+    }
+    return 4;
+}
+
 /// now just some functions to make the thumb opcodes and arm opcodes easier
 pub fn add_with_carry(a: u32, b: u32, carry: bool)
 -> (u32, bool, bool, bool, bool) {
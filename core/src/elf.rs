@@ -0,0 +1,345 @@
+use std::fmt;
+use crate::mem::carts::LoadError;
+
+const ELF_MAGIC: &[u8] = b"\x7FELF";
+const ELF_CLASS_32: u8 = 1;
+const ELF_LITTLE_ENDIAN: u8 = 1;
+const ELF_MACHINE_ARM: u16 = 40;
+const PROGRAM_LOAD: u32 = 1;
+const SECTION_SYMBOL_TABLE: u32 = 2;
+const SYMBOL_OBJECT: u8 = 1;
+const SYMBOL_FUNCTION: u8 = 2;
+const SYMBOL_SIZE: usize = 16;
+// the only places a segment can load to, each one has to fit inside one of these.
+// The last three are the ROM and its mirrors
+const LOAD_REGIONS: [(u32, u32); 9] = [
+    (0x2000000, 0x2040000),
+    (0x3000000, 0x3008000),
+    (0x4000000, 0x4000400),
+    (0x5000000, 0x5000400),
+    (0x6000000, 0x6018000),
+    (0x7000000, 0x7000400),
+    (0x8000000, 0xA000000),
+    (0xA000000, 0xC000000),
+    (0xC000000, 0xE000000),
+];
+
+/// a function or variable from the ELF's symbol table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    // thumb functions have bit 0 cleared here, it's only set in the ELF
+    pub address: u32,
+    pub size: u32,
+    pub is_function: bool,
+}
+
+/// everything the ELF gave names to, sorted by address
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|symbol| symbol.address);
+        Self { symbols }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.symbols.iter().find(|symbol| symbol.name == name).map(|symbol| symbol.address)
+    }
+
+    /// the symbol `address` is inside of and how far into it, symbols without
+    /// a size are taken to go on until the next one starts
+    pub fn lookup(&self, address: u32) -> Option<(&Symbol, u32)> {
+        let index = self.symbols.partition_point(|symbol| symbol.address <= address);
+        let symbol = self.symbols[..index].last()?;
+        let offset = address - symbol.address;
+        if symbol.size != 0 && offset >= symbol.size {
+            return None;
+        }
+        return Some((symbol, offset));
+    }
+
+    /// `function+0x12` if the address has a symbol, otherwise just the address
+    pub fn describe(&self, address: u32) -> String {
+        match self.lookup(address) {
+            Some((symbol, 0)) => symbol.name.clone(),
+            Some((symbol, offset)) => format!("{}+0x{offset:X}", symbol.name),
+            None => format!("{address:08X}"),
+        }
+    }
+}
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08X} {}", self.address, self.name)
+    }
+}
+
+/// what an ELF turns into once its segments have been placed where they load to
+pub struct ElfImage {
+    // everything which loads into 0x8000000 onwards, empty if nothing does
+    pub rom: Vec<u8>,
+    // (address, data) for anything which loads straight into RAM
+    pub ram_segments: Vec<(u32, Vec<u8>)>,
+    pub entry: u32,
+    pub symbols: SymbolTable,
+}
+
+struct ElfReader<'a> {
+    elf: &'a [u8],
+}
+impl<'a> ElfReader<'a> {
+    fn bytes(&self, offset: usize, length: usize) -> Result<&'a [u8], LoadError> {
+        self.elf.get(offset..offset + length).ok_or(LoadError::BadElf("it ends too early"))
+    }
+    fn u8(&self, offset: usize) -> Result<u8, LoadError> {
+        Ok(self.bytes(offset, 1)?[0])
+    }
+    fn u16(&self, offset: usize) -> Result<u16, LoadError> {
+        let bytes = self.bytes(offset, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+    fn u32(&self, offset: usize) -> Result<u32, LoadError> {
+        let bytes = self.bytes(offset, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    fn string(&self, offset: usize) -> Result<String, LoadError> {
+        let rest = self.elf.get(offset..).ok_or(LoadError::BadElf("a name is outside of the file"))?;
+        let length = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+        Ok(String::from_utf8_lossy(&rest[..length]).into_owned())
+    }
+}
+
+/// only 32 bit little endian ARM ELFs, which is what devkitARM makes. The segments
+/// go where their physical address says, so initialised data ends up in the ROM
+/// for the startup code to copy just like it would from a real cart
+pub fn parse_elf(elf: &[u8]) -> Result<ElfImage, LoadError> {
+    let reader = ElfReader { elf };
+    if !elf.starts_with(ELF_MAGIC) {
+        return Err(LoadError::BadElf("it doesn't start with the ELF magic"));
+    }
+    if reader.u8(4)? != ELF_CLASS_32 || reader.u8(5)? != ELF_LITTLE_ENDIAN {
+        return Err(LoadError::BadElf("it isn't 32 bit little endian"));
+    }
+    if reader.u16(18)? != ELF_MACHINE_ARM {
+        return Err(LoadError::BadElf("it isn't for ARM"));
+    }
+
+    let entry = reader.u32(24)?;
+    let program_headers = reader.u32(28)? as usize;
+    let section_headers = reader.u32(32)? as usize;
+    let program_header_size = reader.u16(42)? as usize;
+    let program_header_count = reader.u16(44)? as usize;
+    let section_header_size = reader.u16(46)? as usize;
+    let section_header_count = reader.u16(48)? as usize;
+
+    let mut rom = Vec::new();
+    let mut ram_segments = Vec::new();
+    for i in 0..program_header_count {
+        let header = program_headers + i * program_header_size;
+        if reader.u32(header)? != PROGRAM_LOAD {
+            continue;
+        }
+        let offset = reader.u32(header + 4)? as usize;
+        let address = reader.u32(header + 12)?;
+        let file_size = reader.u32(header + 16)? as usize;
+        let memory_size = reader.u32(header + 20)? as usize;
+        if memory_size == 0 {
+            continue;
+        }
+
+        // checked before anything gets allocated, a broken size could be anything
+        let end = address as u64 + memory_size.max(file_size) as u64;
+        let fits = LOAD_REGIONS.iter().any(|(start, region_end)| address >= *start && end <= *region_end as u64);
+        if !fits {
+            return Err(LoadError::BadElf("a segment doesn't fit anywhere that can be loaded to"));
+        }
+
+        let mut data = reader.bytes(offset, file_size)?.to_vec();
+        match address >= 0x8000000 {
+            // only what's in the file, zeroes past it could cover another segment
+            true => {
+                let start = (address & 0x1FFFFFF) as usize;
+                if rom.len() < start + data.len() {
+                    rom.resize(start + data.len(), 0);
+                }
+                rom[start..start + data.len()].copy_from_slice(&data);
+            }
+            // anything past what's in the file is zeroed, like .bss
+            false => {
+                data.resize(memory_size.max(file_size), 0);
+                ram_segments.push((address, data));
+            }
+        }
+    }
+
+    let mut symbols = Vec::new();
+    for i in 0..section_header_count {
+        let header = section_headers + i * section_header_size;
+        if reader.u32(header + 4)? != SECTION_SYMBOL_TABLE {
+            continue;
+        }
+        let table = reader.u32(header + 16)? as usize;
+        let table_size = reader.u32(header + 20)? as usize;
+        // the section holding the names
+        let link = reader.u32(header + 24)? as usize;
+        let names = reader.u32(section_headers + link * section_header_size + 16)? as usize;
+
+        for symbol in (table..table + table_size).step_by(SYMBOL_SIZE) {
+            let kind = reader.u8(symbol + 12)? & 0xF;
+            let section = reader.u16(symbol + 14)?;
+            if section == 0 || (kind != SYMBOL_FUNCTION && kind != SYMBOL_OBJECT && kind != 0) {
+                continue;
+            }
+            let name = reader.string(names + reader.u32(symbol)? as usize)?;
+            // $a, $t and $d just mark where ARM, thumb and data start
+            if name.is_empty() || name.starts_with('$') {
+                continue;
+            }
+            symbols.push(Symbol {
+                name,
+                address: reader.u32(symbol + 4)? & !1,
+                size: reader.u32(symbol + 8)?,
+                is_function: kind == SYMBOL_FUNCTION,
+            });
+        }
+    }
+
+    Ok(ElfImage {
+        rom,
+        ram_segments,
+        entry,
+        symbols: SymbolTable::new(symbols),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u16(elf: &mut [u8], offset: usize, value: u16) {
+        elf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+    fn put_u32(elf: &mut [u8], offset: usize, value: u32) {
+        elf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// a ROM segment, a RAM segment with some .bss after it, and a symbol table
+    fn build_elf(ram_address: u32) -> Vec<u8> {
+        const PROGRAM_HEADERS: usize = 52;
+        const ROM_DATA: usize = PROGRAM_HEADERS + 2 * 32;
+        const RAM_DATA: usize = ROM_DATA + 8;
+        const NAMES: usize = RAM_DATA + 4;
+        let names = b"\0$a\0$t\0main\0buffer\0table\0ext\0";
+        let symbol_table = NAMES + names.len();
+        // (name, value, size, kind, section)
+        let symbols: [(u32, u32, u32, u8, u16); 7] = [
+            (0, 0, 0, 0, 0),
+            (1, 0x8000000, 0, 0, 1),
+            (4, 0x8000004, 0, 0, 1),
+            (7, 0x8000005, 4, SYMBOL_FUNCTION, 1),
+            (12, 0x3000000, 0, SYMBOL_OBJECT, 2),
+            (19, 0x3000010, 4, SYMBOL_OBJECT, 2),
+            // undefined, it's somewhere else
+            (25, 0, 0, SYMBOL_FUNCTION, 0),
+        ];
+        let section_headers = symbol_table + symbols.len() * SYMBOL_SIZE;
+        let mut elf = vec![0; section_headers + 3 * 40];
+
+        elf[..4].copy_from_slice(ELF_MAGIC);
+        elf[4] = ELF_CLASS_32;
+        elf[5] = ELF_LITTLE_ENDIAN;
+        put_u16(&mut elf, 18, ELF_MACHINE_ARM);
+        put_u32(&mut elf, 24, 0x8000005);
+        put_u32(&mut elf, 28, PROGRAM_HEADERS as u32);
+        put_u32(&mut elf, 32, section_headers as u32);
+        put_u16(&mut elf, 42, 32);
+        put_u16(&mut elf, 44, 2);
+        put_u16(&mut elf, 46, 40);
+        put_u16(&mut elf, 48, 3);
+
+        for (i, (offset, address, file_size, memory_size)) in [
+            (ROM_DATA, 0x8000000, 8, 16),
+            (RAM_DATA, ram_address, 4, 8),
+        ].into_iter().enumerate() {
+            let header = PROGRAM_HEADERS + i * 32;
+            put_u32(&mut elf, header, PROGRAM_LOAD);
+            put_u32(&mut elf, header + 4, offset as u32);
+            put_u32(&mut elf, header + 12, address);
+            put_u32(&mut elf, header + 16, file_size);
+            put_u32(&mut elf, header + 20, memory_size);
+        }
+        elf[ROM_DATA..ROM_DATA + 8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        elf[RAM_DATA..RAM_DATA + 4].copy_from_slice(&[0xA, 0xB, 0xC, 0xD]);
+        elf[NAMES..NAMES + names.len()].copy_from_slice(names);
+
+        for (i, (name, value, size, kind, section)) in symbols.into_iter().enumerate() {
+            let symbol = symbol_table + i * SYMBOL_SIZE;
+            put_u32(&mut elf, symbol, name);
+            put_u32(&mut elf, symbol + 4, value);
+            put_u32(&mut elf, symbol + 8, size);
+            elf[symbol + 12] = kind;
+            put_u16(&mut elf, symbol + 14, section);
+        }
+
+        // section 1 is the symbol table, linked to the names in section 2
+        let header = section_headers + 40;
+        put_u32(&mut elf, header + 4, SECTION_SYMBOL_TABLE);
+        put_u32(&mut elf, header + 16, symbol_table as u32);
+        put_u32(&mut elf, header + 20, (symbols.len() * SYMBOL_SIZE) as u32);
+        put_u32(&mut elf, header + 24, 2);
+        let header = section_headers + 80;
+        put_u32(&mut elf, header + 4, 3);
+        put_u32(&mut elf, header + 16, NAMES as u32);
+        put_u32(&mut elf, header + 20, names.len() as u32);
+        return elf;
+    }
+
+    #[test]
+    fn segments_and_entry() {
+        let image = parse_elf(&build_elf(0x3000000)).unwrap();
+        assert_eq!(image.entry, 0x8000005);
+        // the ROM isn't padded out to the segment's memory size
+        assert_eq!(image.rom, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(image.ram_segments, [(0x3000000, vec![0xA, 0xB, 0xC, 0xD, 0, 0, 0, 0])]);
+    }
+
+    #[test]
+    fn symbols() {
+        let image = parse_elf(&build_elf(0x3000000)).unwrap();
+        let names: Vec<&str> = image.symbols.symbols().iter().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(names, ["buffer", "table", "main"]);
+
+        let main = image.symbols.lookup(0x8000004).unwrap().0;
+        assert_eq!(main.address, 0x8000004);
+        assert!(main.is_function);
+        assert_eq!(image.symbols.address_of("main"), Some(0x8000004));
+        assert_eq!(image.symbols.describe(0x8000006), "main+0x2");
+        assert_eq!(image.symbols.describe(0x8000008), "08000008");
+    }
+
+    #[test]
+    fn lookup_without_a_size() {
+        let image = parse_elf(&build_elf(0x3000000)).unwrap();
+        // buffer has no size, so it covers everything up to table
+        let (symbol, offset) = image.symbols.lookup(0x300000C).unwrap();
+        assert_eq!((symbol.name.as_str(), offset), ("buffer", 0xC));
+        assert_eq!(image.symbols.lookup(0x3000010).unwrap().0.name, "table");
+        assert!(image.symbols.lookup(0x3000014).is_none());
+        assert!(image.symbols.lookup(0x2FFFFFF).is_none());
+    }
+
+    #[test]
+    fn unloadable_segments() {
+        for address in [0x0, 0xE000000, 0x3007FFC] {
+            assert!(matches!(parse_elf(&build_elf(address)), Err(LoadError::BadElf(_))));
+        }
+    }
+}
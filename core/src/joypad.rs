@@ -87,4 +87,5 @@ fn joypad_interrupt(mem: &mut Box<InternalMemory>, joypad: u16) {
     i_flag |= (call_interrupt as u16) << 12;
 
     mem.sys_write_u16(0x4000202, i_flag); 
+    mem.irq_changed = true;
 }
@@ -3,11 +3,14 @@ pub mod ppu;
 pub mod joypad;
 pub mod apu;
 pub mod mem;
+pub mod elf;
+pub mod scheduler;
 
 use cpu::{
     execute_arm::execute_arm, 
     execute_thumb::execute_thumb, 
     handle_interrupts, 
+    hle::HLE_BIOS,
     Cpu, Fde,
 };
 
@@ -15,25 +18,95 @@ use mem::bus::*;
 use joypad::init_joypad;
 use ppu::*;
 
-use crate::{apu::tick_apu, mem::memory::{self, dma_tick, update_timer}};
+use crate::{apu::{fifo_timer_overflow, sample_period, tick_apu, Apu, ApuRegisters}, mem::memory::{self, dma_tick, timer_overflow}};
+use crate::elf::{parse_elf, SymbolTable};
+use crate::mem::carts::{cartridge_from_rom, load_cartridge, load_rom, Cartridge, EmptySlot, LoadError, RomHeader, RumbleCallback, SaveType, TimeSource, HEADER_SIZE};
+use crate::mem::memory::{InternalMemory, MemLengths};
+use crate::scheduler::{Event, Scheduler};
+use std::{fs, io, path::Path};
+
+// DMAs take this long to get going after whatever started them
+const DMA_START_DELAY: u64 = 2;
+// the RTC's offset is kept next to the save with this extension instead
+const RTC_EXTENSION: &str = "rtc";
 
 pub struct Emulator {
     pub cpu: Cpu,
     pub ppu: Ppu,
+    pub apu: Apu,
     pub bus: Bus,
     pub fde: Fde,
-    pub cycles: u32,
+    pub scheduler: Scheduler,
+    // IE and IF have a bit in common, the CPU takes it once IME and the I bit let it
+    irq_pending: bool,
+    // the time the APU has been caught up to
+    apu_cycles: u64,
+    pub header: RomHeader,
+    // only ELFs come with these, it's empty for anything else
+    pub symbols: SymbolTable,
 }
 impl Emulator {
-    pub fn new(filename: &str, from_bios: bool) -> Self {
+    /// the ROM can be raw or inside a .zip or .gz, see `load_cartridge` for how `patch` is used
+    pub fn new(path: &Path, patch: Option<&Path>, from_bios: bool) -> Result<Self, LoadError> {
+        Ok(Self::from_cartridge(load_cartridge(path, patch)?, from_bios))
+    }
+    /// multiboot images get copied into EWRAM and run from there with nothing in the cart
+    /// slot, the same as the BIOS would leave things after receiving one over the link cable
+    pub fn multiboot(path: &Path) -> Result<Self, LoadError> {
+        let image = load_rom(path)?;
+        if image.len() > MemLengths::EWRAM {
+            return Err(LoadError::TooLarge { size: image.len(), max: MemLengths::EWRAM });
+        }
+
+        let mut emulator = Self::from_cartridge(Box::new(EmptySlot), false);
+        emulator.bus.mem.ewram[..image.len()].copy_from_slice(&image);
+        emulator.cpu.pc = 0x2000000;
+        // the header is the same as a cart's, it's just not at 0x8000000
+        emulator.header = RomHeader::parse(&image);
+        emulator.header.entry_point = emulator.header.entry_point.map(|entry| entry - 0x6000000);
+        Ok(emulator)
+    }
+    /// ELFs (from devkitARM for example) have each segment copied to where it loads, and start
+    /// at their entry point rather than the start of the ROM. Their symbols are kept for debugging
+    pub fn from_elf(path: &Path) -> Result<Self, LoadError> {
+        let image = parse_elf(&fs::read(path)?)?;
+        let cart: Box<dyn Cartridge> = match image.rom.is_empty() {
+            true => Box::new(EmptySlot),
+            false => cartridge_from_rom(image.rom),
+        };
+
+        let mut emulator = Self::from_cartridge(cart, false);
+        for (address, data) in &image.ram_segments {
+            for (i, byte) in data.iter().enumerate() {
+                emulator.bus.mem.sys_write_u8(address + i as u32, *byte);
+            }
+        }
+        // bit 0 of the entry point means it's thumb code
+        emulator.cpu.pc = image.entry & !1;
+        emulator.cpu.cpsr.t = image.entry & 1 == 1;
+        emulator.symbols = image.symbols;
+        Ok(emulator)
+    }
+    /// anything implementing `Cartridge` can be plugged in here,
+    /// `new` is just this with the cart read from a file
+    pub fn from_cartridge(cart: Box<dyn Cartridge>, from_bios: bool) -> Self {
         let cpu = match from_bios {
             true => Cpu::from_bios(),
             false => Cpu::new(),
         };
         let ppu = Ppu::new();
-        let mut memory = memory::create_memory(filename);
+        let apu = Apu::new();
+        let header_bytes: Vec<u8> = (0..HEADER_SIZE as u32).map(|i| cart.peek(0x8000000 + i)).collect();
+        let header = RomHeader::parse(&header_bytes);
+        let mut memory = memory::create_memory(cart);
         init_joypad(&mut memory);
-        memory.sys_write_u16(0x4000088, 0b0000_0010_0000_0000);
+        if !from_bios {
+            memory.skip_bios();
+        }
+
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(Event::HBlank, HDRAW_CYCLES);
+        scheduler.schedule(Event::ApuSample, apu_sample_period(&memory));
 
         let bus = Bus::new(memory, from_bios);
         let fde = Fde::new();
@@ -41,45 +114,247 @@ impl Emulator {
         Self {
             cpu,
             ppu,
+            apu,
             bus,
             fde,
-            cycles: 0,
+            scheduler,
+            irq_pending: false,
+            apu_cycles: 0,
+            header,
+            symbols: SymbolTable::default(),
+        }
+    }
+
+    /// what the cart uses to save, either detected from the ROM or whatever a custom cart says
+    pub fn save_type(&self) -> SaveType {
+        self.bus.mem.cart.save_type()
+    }
+
+    /// the cart's backup memory in the raw format other emulators use
+    pub fn save_data(&self) -> &[u8] {
+        self.bus.mem.cart.save_data()
+    }
+    /// changes every time the game writes to its backup memory
+    pub fn save_writes(&self) -> u64 {
+        self.bus.mem.cart.save_writes()
+    }
+    /// the time the game set the RTC to comes from a .rtc file next to the save, if there is one
+    pub fn import_save(&mut self, path: &Path) -> io::Result<()> {
+        let data = fs::read(path)?;
+        self.bus.mem.cart.load_save_data(&data);
+
+        let rtc_path = path.with_extension(RTC_EXTENSION);
+        let Some(rtc) = self.bus.mem.cart.gpio().and_then(|gpio| gpio.rtc.as_mut()) else { return Ok(()); };
+        if !rtc_path.exists() {
+            return Ok(());
         }
+        let offset = fs::read_to_string(&rtc_path)?.trim().parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        rtc.set_game_offset(offset);
+        Ok(())
+    }
+    /// carts without anything to save don't create a file, ones with an RTC get a .rtc as well
+    pub fn export_save(&self, path: &Path) -> io::Result<()> {
+        if let Some(offset) = self.bus.mem.cart.rtc_offset() {
+            fs::write(path.with_extension(RTC_EXTENSION), format!("{offset}\n"))?;
+        }
+        let data = self.save_data();
+        if data.is_empty() {
+            return Ok(());
+        }
+        fs::write(path, data)
+    }
+
+    /// does nothing for carts without an RTC
+    pub fn set_rtc_time_source(&mut self, time_source: TimeSource) {
+        let Some(gpio) = self.bus.mem.cart.gpio() else { return; };
+        if let Some(rtc) = &mut gpio.rtc {
+            rtc.time_source = time_source;
+        }
+    }
+
+    /// how much light Boktai's solar sensor sees, 0 is none and 0xFF is direct sunlight
+    pub fn set_light_level(&mut self, level: u8) {
+        let Some(gpio) = self.bus.mem.cart.gpio() else { return; };
+        if let Some(solar) = &mut gpio.solar {
+            solar.light_level = level;
+        }
+    }
+    /// how far the GBA is tilted, both from -1 to 1 with positive being right and down
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        let Some(tilt) = self.bus.mem.cart.tilt() else { return; };
+        tilt.x = x;
+        tilt.y = y;
+    }
+    /// how fast the GBA is being turned for WarioWare Twisted, from -1 to 1
+    pub fn set_rotation(&mut self, rotation: f32) {
+        let Some(gpio) = self.bus.mem.cart.gpio() else { return; };
+        if let Some(gyro) = &mut gpio.gyro {
+            gyro.rotation = rotation;
+        }
+    }
+    /// called with true when the game turns the rumble motor on and false when it stops
+    pub fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        let Some(gpio) = self.bus.mem.cart.gpio() else { return; };
+        if let Some(rumble) = &mut gpio.rumble {
+            rumble.callback = Some(callback);
+        }
+    }
+
+    /// runs SWIs natively rather than through the BIOS, and swaps the BIOS for a stand in
+    /// with just the interrupt handler in it. Nothing of the real BIOS gets used like this
+    pub fn set_hle_bios(&mut self, enabled: bool) {
+        self.cpu.hle_bios = enabled;
+        self.bus.mem.bios = match enabled {
+            true => &HLE_BIOS,
+            false => memory::BIOS,
+        };
+    }
+    /// prints every SWI the game makes to stderr, by name and with r0 to r3
+    pub fn set_swi_trace(&mut self, enabled: bool) {
+        self.cpu.swi_trace = enabled.then(|| self.symbols.clone());
+    }
+
+    /// the rate samples are produced at for `drain_audio`, this
+    /// should match whatever is going to play them
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.mixer.set_sample_rate(sample_rate);
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.apu.mixer.sample_rate()
+    }
+    /// small adjustments around 1.0 to the resampling, see `Mixer::set_rate_ratio`
+    pub fn set_audio_rate_ratio(&mut self, ratio: f64) {
+        self.apu.mixer.set_rate_ratio(ratio);
+    }
+
+    /// copies as many interleaved (left, right) samples as are ready into `out`,
+    /// returning how many i16's were written. Nothing here needs an audio device
+    /// so it works the same for the frontend or anything running headless
+    pub fn drain_audio(&mut self, out: &mut [i16]) -> usize {
+        self.apu.mixer.buffer.drain(out)
+    }
+    /// how many stereo frames are waiting to be drained
+    pub fn queued_audio_frames(&self) -> usize {
+        self.apu.mixer.buffer.frames()
     }
 }
 
+/// runs one instruction, or one unit of DMA, then anything the scheduler has due by then.
+/// While halted it skips straight to the next event. Returns true once a frame is done
 pub fn run_single_step(emu: &mut Emulator) -> bool {
-    // update the timer
-    // add 1 for now, make it more accurate later
-    update_timer(&mut emu.bus.mem, &mut emu.cycles, 20);
-    let active_dma = dma_tick(&mut emu.bus.mem);
+    emu.bus.mem.now = emu.scheduler.now();
+
+    // DMA has the bus to itself, so the CPU can't do anything until it's done
+    let dma_cycles = match emu.bus.mem.dma_running() {
+        true => dma_tick(&mut emu.bus.mem),
+        false => None,
+    };
+    let cycles = match dma_cycles {
+        Some(cycles) => cycles,
+        None => {
+            if emu.irq_pending {
+                handle_interrupts(&mut emu.bus, &mut emu.cpu);
+            }
+            match emu.cpu.halted {
+                // nothing can wake it up before then
+                true => (emu.scheduler.next_event_time() - emu.scheduler.now()).clamp(1, u32::MAX as u64) as u32,
+                false => handle_cpu(&mut emu.cpu, &mut emu.bus),
+            }
+        }
+    };
+    if emu.bus.should_halt_cpu() {
+        emu.cpu.halted = true;
+    }
+
+    emu.scheduler.advance(cycles);
+    emu.bus.mem.now = emu.scheduler.now();
+    reschedule(emu);
+    while let Some((event, time)) = emu.scheduler.pop_due() {
+        handle_event(emu, event, time);
+        reschedule(emu);
+    }
 
-    tick_apu();
-    tick_ppu(&mut emu.ppu, &mut emu.bus);
     if emu.ppu.new_screen {
         emu.ppu.new_screen = false;
         return true;
     }
-    if active_dma {
-        return false;
+    return false;
+}
+
+/// picks up on whatever the CPU (or an event) changed which moves other events around
+fn reschedule(emu: &mut Emulator) {
+    let timers_changed = std::mem::take(&mut emu.bus.mem.timers_changed);
+    for timer in 0..4 {
+        if (timers_changed >> timer) & 1 == 0 {
+            continue;
+        }
+        emu.scheduler.cancel(Event::TimerOverflow(timer));
+        if let Some(time) = emu.bus.mem.timer_overflow_time(timer) {
+            emu.scheduler.schedule_at(Event::TimerOverflow(timer), time);
+        }
     }
 
-    handle_interrupts(&mut emu.bus, &mut emu.cpu);
-    if emu.cpu.halted {
-        return false;
+    let dma_triggered = std::mem::take(&mut emu.bus.mem.dma_triggered);
+    for channel in 0..4 {
+        if (dma_triggered >> channel) & 1 == 1 {
+            emu.scheduler.cancel(Event::DmaStart(channel));
+            emu.scheduler.schedule(Event::DmaStart(channel), DMA_START_DELAY);
+        }
     }
 
-    handle_cpu(&mut emu.cpu, &mut emu.bus);
-    if emu.bus.should_halt_cpu() {
-        emu.cpu.halted = true;
+    if std::mem::take(&mut emu.bus.mem.irq_changed) {
+        emu.scheduler.cancel(Event::Irq);
+        emu.scheduler.schedule(Event::Irq, 0);
     }
-    return false;
 }
 
-pub fn handle_cpu<M: CpuInterface>(cpu: &mut Cpu, mem: &mut M) {
+fn handle_event(emu: &mut Emulator, event: Event, time: u64) {
+    match event {
+        Event::TimerOverflow(timer) => {
+            let overflows = timer_overflow(&mut emu.bus.mem, timer, time);
+            fifo_timer_overflow(&mut emu.bus.mem, overflows);
+            emu.bus.mem.timers_changed |= 1 << timer;
+        }
+        Event::HBlank => {
+            start_hblank(&mut emu.ppu, &mut emu.bus);
+            // H-blank DMA doesn't happen during V-blank
+            if emu.bus.mem.sys_read_u16(0x4000006) < 160 {
+                emu.bus.mem.trigger_dma(2);
+            }
+            emu.scheduler.schedule_at(Event::LineEnd, time + HBLANK_CYCLES);
+        }
+        Event::LineEnd => {
+            end_line(&mut emu.ppu, &mut emu.bus);
+            if emu.bus.mem.sys_read_u16(0x4000006) == 160 {
+                emu.bus.mem.trigger_dma(1);
+            }
+            emu.scheduler.schedule_at(Event::HBlank, time + HDRAW_CYCLES);
+        }
+        Event::ApuSample => {
+            let elapsed = time - emu.apu_cycles;
+            emu.apu_cycles = time;
+            tick_apu(&mut emu.apu, &mut emu.bus, elapsed as u32);
+            emu.scheduler.schedule_at(Event::ApuSample, time + apu_sample_period(&emu.bus.mem));
+        }
+        Event::DmaStart(channel) => emu.bus.mem.start_dma(channel),
+        Event::Irq => emu.irq_pending = emu.bus.mem.interrupt_pending(),
+    }
+}
+
+fn apu_sample_period(mem: &InternalMemory) -> u64 {
+    let soundbias = mem.sys_read_u16(ApuRegisters::SoundBias as u32);
+    return sample_period(soundbias) as u64;
+}
+
+/// runs the decoded instruction and refills the pipeline, returning the cycles both took
+pub fn handle_cpu<M: CpuInterface>(cpu: &mut Cpu, mem: &mut M) -> u32 {
     // Execute
+    let mut cycles = 0;
+    let (next_fetch, was_thumb) = (cpu.pc, cpu.cpsr.t);
+    let executed = cpu.fde.decoded_opcode.is_some();
     if let Some(instruction) = cpu.fde.decoded_opcode {        
-        match cpu.cpsr.t {
+        cycles = match cpu.cpsr.t {
             true => {
                 // println!("{}", assemblify::to_thumb_assembly(instruction as u16));
                 execute_thumb(instruction as u16, cpu, mem)
@@ -92,19 +367,29 @@ pub fn handle_cpu<M: CpuInterface>(cpu: &mut Cpu, mem: &mut M) {
     }
     
     // if there was a clear, need to get new fetched
-    if let None = cpu.fde.fetched_opcode {
+    let start = mem.access_cycles();
+    if cpu.fde.fetched_opcode.is_none() {
+        // the opcode after a branch is still fetched while it works out where to go, it just gets thrown away
+        if executed {
+            match was_thumb {
+                true => { mem.fetch_u16(next_fetch); }
+                false => { mem.fetch_u32(next_fetch); }
+            }
+        }
+
         let fetch = match cpu.cpsr.t {
-            true => mem.read_u16(cpu.get_pc_thumb()) as u32,
-            false => mem.read_u32_unrotated(cpu.get_pc_arm()),
+            true => mem.fetch_u16(cpu.get_pc_thumb()) as u32,
+            false => mem.fetch_u32(cpu.get_pc_arm()),
         };
         cpu.fde.fetched_opcode = Some(fetch);
     }
     
     // move the fetched to decoded
-    cpu.fde.decoded_opcode = cpu.fde.fetched_opcode.clone();
+    cpu.fde.decoded_opcode = cpu.fde.fetched_opcode;
     let fetch = match cpu.cpsr.t {
-        true => mem.read_u16(cpu.get_pc_thumb()) as u32,
-        false => mem.read_u32_unrotated(cpu.get_pc_arm()),
+        true => mem.fetch_u16(cpu.get_pc_thumb()) as u32,
+        false => mem.fetch_u32(cpu.get_pc_arm()),
     };
     cpu.fde.fetched_opcode = Some(fetch);
+    return cycles + mem.access_cycles().wrapping_sub(start);
 }
\ No newline at end of file
@@ -4,17 +4,34 @@ use crate::mem::lil_end_combine_u32;
 use crate::mem::lil_end_split_u16;
 use crate::mem::lil_end_split_u32;
 use crate::mem::memory::InternalMemory;
+use crate::mem::prefetch::Prefetch;
 use crate::mem::split_memory_address;
 
 pub trait CpuInterface {
-    fn read_u8(&self, address: u32) -> u8;
-    fn read_u16(&self, address: u32) -> u16;
-    fn read_u32_unrotated(&self, address: u32) -> u32;
-    fn read_u32_rotated(&self, address: u32) -> u32;
+    fn read_u8(&mut self, address: u32) -> u8;
+    fn read_u16(&mut self, address: u32) -> u16;
+    fn read_u32_unrotated(&mut self, address: u32) -> u32;
+    fn read_u32_rotated(&mut self, address: u32) -> u32;
 
     fn write_u8(&mut self, address: u32, data: u8);
     fn write_u16(&mut self, address: u32, data: u16);
     fn write_u32(&mut self, address: u32, data: u32);
+
+    /// opcode fetches, these are the same as any other read apart from how long they take
+    fn fetch_u16(&mut self, address: u32) -> u16 {
+        self.read_u16(address)
+    }
+    fn fetch_u32(&mut self, address: u32) -> u32 {
+        self.read_u32_unrotated(address)
+    }
+    /// the CPU spent `cycles` on I cycles and left the bus alone
+    fn idle(&mut self, _cycles: u32) {}
+
+    /// a running total of the cycles every access has taken, only the
+    /// difference between two calls means anything. Untimed memory is always free
+    fn access_cycles(&self) -> u32 {
+        0
+    }
 }
 pub trait PpuInterface {
     fn read_vram_u8(&self, address: u32) -> u8;
@@ -22,6 +39,8 @@ pub trait PpuInterface {
     fn read_vram_u32(&self, address: u32) -> u32;
 
     fn write_vram_u16(&mut self, address: u32, data: u16);
+    /// sets bits in IF
+    fn request_interrupt(&mut self, bits: u16);
 }
 
 // the area that it writes/reads from can affect
@@ -32,8 +51,12 @@ pub enum MemoryRegion {
     WramBoard,
     WramChip,
     IoReg,
+    Palette,
+    Vram,
+    Oam,
     Rom,
     Sram,
+    Unmapped,
 }
 impl MemoryRegion {
     fn from_pc(pc: u32) -> MemoryRegion {
@@ -44,29 +67,44 @@ impl MemoryRegion {
             2 => WramBoard,
             3 => WramChip,
             4 => IoReg,
+            5 => Palette,
+            6 => Vram,
+            7 => Oam,
             8..=0xD => Rom,
-            0xE => Sram,
-            _ => unreachable!("code should never execute here"),
+            0xE | 0xF => Sram,
+            // it can still end up running from here, it'll just be open bus
+            _ => Unmapped,
         }
     }
 }
 
+// msr spsr_fc, r0
+const BIOS_EXIT_FETCH: u32 = 0xE129F000;
+
 pub struct Bus {
-    last_bios_fetch: u32,
-    pc_fetched_area: MemoryRegion,
+    // the most recent opcode fetch, which is the one after the instruction being executed
     last_fetched_opcode: u32,
+    last_fetch_address: u32,
+    thumb_fetch: bool,
     pub mem: Box<InternalMemory>,
     should_halt_cpu: bool,
+    access_cycles: u32,
+    // an access here is sequential, anything else is non-sequential
+    next_sequential_address: u32,
+    prefetch: Prefetch,
 }
 
 impl Bus {
     pub fn new(mem: Box<InternalMemory>, from_bios: bool) -> Self {
         let mut default = Self {
-            last_bios_fetch: 0x0,
-            pc_fetched_area: MemoryRegion::Bios,
             last_fetched_opcode: 0x0,
+            last_fetch_address: 0x0,
+            thumb_fetch: false,
             mem,
             should_halt_cpu: false,
+            access_cycles: 0,
+            next_sequential_address: 0,
+            prefetch: Prefetch::new(),
         };
 
         // starting from the bios
@@ -74,29 +112,54 @@ impl Bus {
             return default;
         }
 
-        let last_fetched = default.fetch_arm_opcode(0xDC + 8);
-        default.last_bios_fetch = last_fetched;
-        default.last_fetched_opcode = last_fetched;
+        // the opcode at 0xE4, which the BIOS has just fetched when it jumps to the cart.
+        // This doesn't come from the BIOS image since it could be the HLE one
+        default.mem.last_bios_fetch = BIOS_EXIT_FETCH;
+        default.mem.executing_bios = false;
+        default.last_fetched_opcode = BIOS_EXIT_FETCH;
         return default;
     }
 
-    pub fn fetch_arm_opcode(&mut self, pc: u32) -> u32 {
-        self.pc_fetched_area = MemoryRegion::from_pc(pc);
-        let opcode = self.mem.sys_read_u32(pc);
-        if let MemoryRegion::Bios = self.pc_fetched_area {
-            self.last_bios_fetch = opcode;
+    /// the BIOS can only be read by code running in it, so this has to know before the fetch happens
+    fn start_fetch(&mut self, pc: u32) {
+        self.mem.executing_bios = MemoryRegion::from_pc(pc) == MemoryRegion::Bios && pc < 0x10000000;
+    }
+    fn record_fetch(&mut self, pc: u32, opcode: u32, thumb: bool) {
+        // the BIOS is always fetched from a word at a time, even for thumb
+        if self.mem.executing_bios {
+            self.mem.last_bios_fetch = self.mem.sys_read_u32(pc);
         }
-        
-        return opcode;
+        self.last_fetched_opcode = opcode;
+        self.last_fetch_address = pc;
+        self.thumb_fetch = thumb;
     }
-    pub fn fetch_thumb_opcode(&mut self, pc: u32) -> u16 {
-        self.pc_fetched_area = MemoryRegion::from_pc(pc);
-        let opcode = self.mem.sys_read_u16(pc);
-        if let MemoryRegion::Bios = self.pc_fetched_area {
-            self.last_bios_fetch = opcode as u32;
+
+    /// unmapped reads see whatever the last opcode fetch left on the bus. In ARM that's the
+    /// opcode at $+8 (where $ is the one executing), thumb fills the other half differently
+    /// depending on the width of the memory the code runs from. The fetch after the one in
+    /// the pipeline hasn't happened yet when the instruction executes here, so it's peeked
+    fn open_bus(&self) -> u32 {
+        let decoded_address = self.last_fetch_address;
+        if !self.thumb_fetch {
+            return self.mem.sys_read_u32(decoded_address.wrapping_add(4));
         }
 
-        return opcode;
+        let executing = decoded_address.wrapping_sub(2);
+        let decoded = self.last_fetched_opcode & 0xFFFF;
+        let next = self.mem.sys_read_u16(decoded_address.wrapping_add(2)) as u32;
+        let aligned = executing & 0b11 == 0;
+        use MemoryRegion::*;
+        match (MemoryRegion::from_pc(executing), aligned) {
+            (Bios | Oam, true) => {
+                let after_next = self.mem.sys_read_u16(decoded_address.wrapping_add(4)) as u32;
+                next | after_next << 16
+            }
+            (Bios | Oam, false) => decoded | next << 16,
+            (WramChip, true) => next | decoded << 16,
+            (WramChip, false) => decoded | next << 16,
+            // everything on a 16 bit bus just has the same halfword twice
+            _ => next | next << 16,
+        }
     }
     
     pub fn sys_write_u16(&mut self, address: u32, data: u16) {
@@ -109,28 +172,73 @@ impl Bus {
         return stored;
     }
 
-    pub fn cpu_read(&self, address: u32) -> u8 {
+    pub fn cpu_read(&mut self, address: u32) -> u8 {
         if let Some(data) = self.mem.cpu_read(address) {
             return data;
         }
         
         // reaching here means the address was invalid
         // and so most recent opcode fetch should be done
-        let rotation_amount = (address & 0x3) * 8;
-        let rotated_op = self.last_fetched_opcode.rotate_right(rotation_amount as u32);
-        return rotated_op as u8;
+        let shift = (address & 0x3) * 8;
+        return (self.open_bus() >> shift) as u8;
     }
     pub fn cpu_write(&mut self, address: u32, data: u8, is_8_bit: bool) {
         self.mem.cpu_write(address, data, is_8_bit);
     }
+
+    /// accesses straight after the previous one are sequential, which the cart can do faster.
+    /// Reading or writing the cart stops the prefetcher, anything else gives it time to run
+    fn add_access(&mut self, address: u32, width: u32) {
+        let sequential = address == self.next_sequential_address;
+        self.next_sequential_address = address.wrapping_add(width);
+        let cycles = self.mem.access_cycles(address, width, sequential);
+        self.access_cycles = self.access_cycles.wrapping_add(cycles);
+
+        let (upp, _) = split_memory_address(address);
+        match upp {
+            0x8..=0xF => self.prefetch.stop(),
+            _ => self.step_prefetch(cycles),
+        }
+    }
+    /// opcodes from the ROM come out of the prefetch buffer when it's on, and
+    /// if it doesn't have them it starts over from the one after
+    fn add_fetch(&mut self, address: u32, width: u32) {
+        let (upp, _) = split_memory_address(address);
+        if !(0x8..=0xD).contains(&upp) || !self.mem.prefetch_enabled() {
+            if !self.mem.prefetch_enabled() {
+                self.prefetch.stop();
+            }
+            self.add_access(address, width);
+            return;
+        }
+
+        let sequential_cycles = self.mem.access_cycles(address, 2, true);
+        let cycles = match self.prefetch.has(address) {
+            true => self.prefetch.take(width, sequential_cycles),
+            false => {
+                let sequential = address == self.next_sequential_address;
+                let cycles = self.mem.access_cycles(address, width, sequential);
+                self.prefetch.restart(address.wrapping_add(width), sequential_cycles);
+                cycles
+            }
+        };
+        self.next_sequential_address = address.wrapping_add(width);
+        self.access_cycles = self.access_cycles.wrapping_add(cycles);
+    }
+    fn step_prefetch(&mut self, cycles: u32) {
+        let sequential_cycles = self.mem.access_cycles(0x8000000, 2, true);
+        self.prefetch.step(cycles, sequential_cycles);
+    }
 }
 
 impl CpuInterface for Bus {
-    fn read_u8(&self, address: u32) -> u8 {
+    fn read_u8(&mut self, address: u32) -> u8 {
+        self.add_access(address, 1);
         self.cpu_read(address)
     }
-    fn read_u16(&self, address: u32) -> u16 {
+    fn read_u16(&mut self, address: u32) -> u16 {
         let base_address = address & !(0b1);
+        self.add_access(base_address, 2);
 
         lil_end_combine_u16(
             self.cpu_read(base_address + 0), 
@@ -140,8 +248,9 @@ impl CpuInterface for Bus {
 
     // I am not too sure if these two functions are needed
     // i may eventually get ride of them
-    fn read_u32_unrotated(&self, address: u32) -> u32 {
+    fn read_u32_unrotated(&mut self, address: u32) -> u32 {
         let base_address = address & !(0b11);
+        self.add_access(base_address, 4);
 
         lil_end_combine_u32(
             self.cpu_read(base_address + 0), 
@@ -150,13 +259,14 @@ impl CpuInterface for Bus {
             self.cpu_read(base_address + 3),
         )
     }
-    fn read_u32_rotated(&self, address: u32) -> u32 {
+    fn read_u32_rotated(&mut self, address: u32) -> u32 {
         self.read_u32_unrotated(address).rotate_right((address & 0b11) * 8)
     }
 
     fn write_u16(&mut self, address: u32, data: u16) {
         let split = lil_end_split_u16(data);
         let address = address & !(0b1);
+        self.add_access(address, 2);
 
         self.cpu_write(address + 0, split.0, false);
         self.cpu_write(address + 1, split.1, false);
@@ -164,6 +274,7 @@ impl CpuInterface for Bus {
     fn write_u32(&mut self, address: u32, data: u32) {
         let split = lil_end_split_u32(data);
         let address = address & !(0b11);
+        self.add_access(address, 4);
 
         self.cpu_write(address + 0, split.0, false);
         self.cpu_write(address + 1, split.1, false);
@@ -172,6 +283,7 @@ impl CpuInterface for Bus {
  
     }
     fn write_u8(&mut self, address: u32, data: u8) {
+        self.add_access(address, 1);
         if address == 0x4000301 {
             self.should_halt_cpu = true;
             return;
@@ -179,6 +291,40 @@ impl CpuInterface for Bus {
 
         self.cpu_write(address, data, true);
     }
+
+    fn fetch_u16(&mut self, address: u32) -> u16 {
+        let base_address = address & !(0b1);
+        self.add_fetch(base_address, 2);
+        self.start_fetch(base_address);
+
+        let opcode = lil_end_combine_u16(
+            self.cpu_read(base_address + 0),
+            self.cpu_read(base_address + 1),
+        );
+        self.record_fetch(base_address, opcode as u32, true);
+        return opcode;
+    }
+    fn fetch_u32(&mut self, address: u32) -> u32 {
+        let base_address = address & !(0b11);
+        self.add_fetch(base_address, 4);
+        self.start_fetch(base_address);
+
+        let opcode = lil_end_combine_u32(
+            self.cpu_read(base_address + 0),
+            self.cpu_read(base_address + 1),
+            self.cpu_read(base_address + 2),
+            self.cpu_read(base_address + 3),
+        );
+        self.record_fetch(base_address, opcode, false);
+        return opcode;
+    }
+    fn idle(&mut self, cycles: u32) {
+        self.step_prefetch(cycles);
+    }
+
+    fn access_cycles(&self) -> u32 {
+        self.access_cycles
+    }
 }
 impl PpuInterface for Bus {
     fn read_vram_u16(&self, address: u32) -> u16 {
@@ -205,4 +351,7 @@ impl PpuInterface for Bus {
         assert!(is_in_video_memory(upp) || upp == 0x4);
         self.mem.sys_write_u16(address, data);
     }
+    fn request_interrupt(&mut self, bits: u16) {
+        self.mem.request_interrupt(bits);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,210 @@
+use crate::mem::carts::{load_flat_save, read_rom, Cartridge, SaveType};
+use crate::mem::split_memory_address;
+
+// the largest EEPROM is 8KB, the smaller one only uses the first 512 bytes
+const EEPROM_SIZE: usize = 0x2000;
+const BLOCK_SIZE: usize = 8;
+// reads give back 4 bits of nothing before the 64 that were asked for
+const DUMMY_READ_BITS: usize = 4;
+const READ_LENGTH: usize = DUMMY_READ_BITS + 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EepromSize {
+    // 6 bit addresses, 64 blocks
+    Small,
+    // 14 bit addresses but only 10 of them get used, 1024 blocks
+    Large,
+}
+impl EepromSize {
+    fn address_bits(&self) -> usize {
+        match self {
+            EepromSize::Small => 6,
+            EepromSize::Large => 14,
+        }
+    }
+    fn blocks(&self) -> usize {
+        match self {
+            EepromSize::Small => 0x40,
+            EepromSize::Large => 0x400,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EepromState {
+    // anything read now is the ready bit
+    Idle,
+    // bits of a request, the first is in the highest position
+    Receiving { bits: u128, count: usize },
+    Reading { data: u64, position: usize },
+}
+
+/// the EEPROM is talked to one bit at a time, which is bit 0 of each halfword
+/// written to (or read from) 0xD000000 onwards. Requests are 2 bits saying
+/// read (11) or write (10), the address, the 64 bits of data for a write,
+/// and then a single 0 to finish
+pub struct EepRom {
+    rom: Vec<u8>,
+    eeprom: Vec<u8>,
+    // not known until the game makes its first request
+    size: Option<EepromSize>,
+    // for ROMs over 16MB the EEPROM only takes the last 256 bytes of 0xD000000
+    is_32mb_rom: bool,
+    state: EepromState,
+    writes: u64,
+}
+impl EepRom {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Self {
+            is_32mb_rom: rom.len() > 0x1000000,
+            rom,
+            eeprom: vec![0xFF; EEPROM_SIZE],
+            size: None,
+            state: EepromState::Idle,
+            writes: 0,
+        }
+    }
+
+    /// how many bytes the EEPROM is, once the game has shown which one it expects
+    pub fn size(&self) -> Option<usize> {
+        self.size.map(|size| size.blocks() * BLOCK_SIZE)
+    }
+
+    fn is_eeprom_address(&self, address: u32) -> bool {
+        let (upp, low) = split_memory_address(address);
+        match self.is_32mb_rom {
+            true => upp == 0xD && low >= 0xFFFF00,
+            false => upp == 0xD,
+        }
+    }
+
+    fn receive_bit(&mut self, bit: u128) {
+        let (bits, count) = match self.state {
+            EepromState::Receiving { bits, count } => ((bits << 1) | bit, count + 1),
+            _ => (bit, 1),
+        };
+        // every request starts with a 1, anything else is just noise
+        if count == 1 && bit == 0 {
+            self.state = EepromState::Idle;
+            return;
+        }
+        self.state = EepromState::Receiving { bits, count };
+        if count < 2 {
+            return;
+        }
+
+        // games which never DMA'd their size are almost always the small one
+        let size = *self.size.get_or_insert(EepromSize::Small);
+        let address_bits = size.address_bits();
+        let is_read = bits >> (count - 2) == 0b11;
+        let request_length = match is_read {
+            true => 2 + address_bits + 1,
+            false => 2 + address_bits + 64 + 1,
+        };
+        if count < request_length {
+            return;
+        }
+
+        // the stop bit is at the bottom, then the data for a write and then the address
+        let data_bits = match is_read {
+            true => 0,
+            false => 64,
+        };
+        let address = (bits >> (1 + data_bits)) as usize & ((1 << address_bits) - 1);
+        let block = (address % size.blocks()) * BLOCK_SIZE;
+
+        match is_read {
+            true => {
+                let mut data = [0; BLOCK_SIZE];
+                data.copy_from_slice(&self.eeprom[block..block + BLOCK_SIZE]);
+                self.state = EepromState::Reading { data: u64::from_be_bytes(data), position: 0 };
+            }
+            false => {
+                let data = (bits >> 1) as u64;
+                self.eeprom[block..block + BLOCK_SIZE].copy_from_slice(&data.to_be_bytes());
+                self.writes += 1;
+                // writing happens straight away, so it's ready as soon as it is asked
+                self.state = EepromState::Idle;
+            }
+        }
+    }
+}
+impl Cartridge for EepRom {
+    fn read(&mut self, address: u32) -> u8 {
+        if !self.is_eeprom_address(address) {
+            return self.peek(address);
+        }
+        // only the bottom bit of the halfword means anything
+        if address & 1 == 1 {
+            return 0;
+        }
+
+        match self.state {
+            EepromState::Reading { data, position } => {
+                self.state = match position + 1 {
+                    READ_LENGTH => EepromState::Idle,
+                    next => EepromState::Reading { data, position: next },
+                };
+                if position < DUMMY_READ_BITS {
+                    return 0;
+                }
+                return (data >> (63 - (position - DUMMY_READ_BITS))) as u8 & 1;
+            }
+            _ => return 1,
+        }
+    }
+    fn peek(&self, address: u32) -> u8 {
+        let (upp, _) = split_memory_address(address);
+        if upp >= 0xE {
+            return 0xFF;
+        }
+        if self.is_eeprom_address(address) {
+            return match address & 1 == 0 && !matches!(self.state, EepromState::Reading { .. }) {
+                true => 1,
+                false => 0,
+            };
+        }
+        read_rom(&self.rom, address)
+    }
+    fn write(&mut self, address: u32, data: u8, _is_8_bit: bool) {
+        if !self.is_eeprom_address(address) {
+            println!("EEPROM: attempted to write {data:X} to address {address:X}");
+            return;
+        }
+        if address & 1 == 1 {
+            return;
+        }
+        self.receive_bit(data as u128 & 1);
+    }
+    fn save_type(&self) -> SaveType { SaveType::Eeprom }
+
+    /// nothing gets saved until the game has shown which size it is
+    fn save_data(&self) -> &[u8] {
+        match self.size() {
+            Some(size) => &self.eeprom[..size],
+            None => &[],
+        }
+    }
+    /// the size of the save says which EEPROM it was
+    fn load_save_data(&mut self, data: &[u8]) {
+        match data.len() {
+            0x200 => self.size = Some(EepromSize::Small),
+            EEPROM_SIZE => self.size = Some(EepromSize::Large),
+            _ => {}
+        }
+        load_flat_save(&mut self.eeprom, data);
+    }
+    fn save_writes(&self) -> u64 { self.writes }
+
+    fn dma_started(&mut self, destination: u32, amount: u32) {
+        if self.size.is_some() || !self.is_eeprom_address(destination) {
+            return;
+        }
+        // the lengths of a read and write request with each address size
+        self.size = match amount {
+            9 | 73 => Some(EepromSize::Small),
+            17 | 81 => Some(EepromSize::Large),
+            _ => None,
+        };
+    }
+}
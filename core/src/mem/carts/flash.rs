@@ -0,0 +1,204 @@
+use crate::mem::carts::{load_flat_save, read_rom, Cartridge, SaveType};
+use crate::mem::split_memory_address;
+
+const BANK_SIZE: usize = 0x10000;
+const SECTOR_SIZE: usize = 0x1000;
+// atmel chips don't have sector erase, writes always replace a whole 128 byte page
+const ATMEL_PAGE_SIZE: usize = 0x80;
+
+/// the chips Nintendo actually put in carts, games check the ID
+/// so this has to be one they know about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlashChip {
+    // 64K
+    Panasonic,
+    Atmel,
+    Macronix64K,
+    // 128K
+    Sanyo,
+    Macronix128K,
+}
+impl FlashChip {
+    /// (manufacturer, device), shown at 0xE000000 and 0xE000001 in ID mode
+    pub fn id(&self) -> (u8, u8) {
+        match self {
+            FlashChip::Panasonic => (0x32, 0x1B),
+            FlashChip::Atmel => (0x1F, 0x3D),
+            FlashChip::Macronix64K => (0xC2, 0x1C),
+            FlashChip::Sanyo => (0x62, 0x13),
+            FlashChip::Macronix128K => (0xC2, 0x09),
+        }
+    }
+    pub fn is_128kb(&self) -> bool {
+        matches!(self, FlashChip::Sanyo | FlashChip::Macronix128K)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FlashState {
+    Ready,
+    // 0xAA has been written to 0x5555
+    Unlock1,
+    // then 0x55 to 0x2AAA, the next write to 0x5555 is the command
+    Unlock2,
+    // the next byte written anywhere is programmed
+    Program,
+    // the next write to 0x0000 picks the bank
+    BankSwitch,
+    // atmel chips take 128 bytes after the program command
+    AtmelPage { start: usize, remaining: usize },
+}
+
+pub struct FlashRom {
+    rom: Vec<u8>,
+    flash: Vec<u8>,
+    chip: FlashChip,
+
+    state: FlashState,
+    id_mode: bool,
+    // 0x80 was the last command, so the next one can be an erase
+    erase_ready: bool,
+    bank: usize,
+    writes: u64,
+}
+impl FlashRom {
+    pub fn new(rom: Vec<u8>, chip: FlashChip) -> Self {
+        let size = match chip.is_128kb() {
+            true => BANK_SIZE * 2,
+            false => BANK_SIZE,
+        };
+        Self {
+            rom,
+            flash: vec![0xFF; size],
+            chip,
+
+            state: FlashState::Ready,
+            id_mode: false,
+            erase_ready: false,
+            bank: 0,
+            writes: 0,
+        }
+    }
+
+    fn command(&mut self, command: u8) {
+        self.state = FlashState::Ready;
+        let erase_ready = self.erase_ready;
+        self.erase_ready = false;
+
+        match command {
+            0x90 => self.id_mode = true,
+            0xF0 => self.id_mode = false,
+            0x80 => self.erase_ready = true,
+            0x10 if erase_ready => {
+                self.flash.fill(0xFF);
+                self.writes += 1;
+            }
+            0xA0 => self.state = FlashState::Program,
+            0xB0 if self.chip.is_128kb() => self.state = FlashState::BankSwitch,
+            _ => println!("FLASH: unknown command {command:X}"),
+        }
+    }
+
+    fn program(&mut self, offset: usize, data: u8) {
+        self.writes += 1;
+        if self.chip != FlashChip::Atmel {
+            self.flash[self.bank * BANK_SIZE + offset] = data;
+            return;
+        }
+
+        let start = offset & !(ATMEL_PAGE_SIZE - 1);
+        let page = self.bank * BANK_SIZE + start;
+        self.flash[page..page + ATMEL_PAGE_SIZE].fill(0xFF);
+        self.state = FlashState::AtmelPage { start, remaining: ATMEL_PAGE_SIZE };
+        self.atmel_program(offset, data);
+    }
+
+    fn atmel_program(&mut self, offset: usize, data: u8) {
+        let FlashState::AtmelPage { start, remaining } = self.state else { return; };
+        if offset & !(ATMEL_PAGE_SIZE - 1) == start {
+            self.flash[self.bank * BANK_SIZE + offset] = data;
+        }
+
+        self.state = match remaining - 1 {
+            0 => FlashState::Ready,
+            remaining => FlashState::AtmelPage { start, remaining },
+        };
+    }
+}
+impl Cartridge for FlashRom {
+    fn read(&mut self, address: u32) -> u8 {
+        self.peek(address)
+    }
+    fn peek(&self, address: u32) -> u8 {
+        let (upp, low) = split_memory_address(address);
+        if upp < 0xE {
+            return read_rom(&self.rom, address);
+        }
+
+        let offset = low % BANK_SIZE;
+        if self.id_mode && offset < 2 {
+            let (manufacturer, device) = self.chip.id();
+            return match offset {
+                0 => manufacturer,
+                _ => device,
+            };
+        }
+        return self.flash[self.bank * BANK_SIZE + offset];
+    }
+    fn write(&mut self, address: u32, data: u8, _is_8_bit: bool) {
+        let (upp, low) = split_memory_address(address);
+        if upp < 0xE {
+            println!("cannot write to ROM {address:X}, {data:X}");
+            return;
+        }
+
+        let offset = low % BANK_SIZE;
+        use FlashState::*;
+        match (self.state, offset, data) {
+            (Program, _, _) => {
+                self.state = Ready;
+                self.program(offset, data);
+            }
+            (AtmelPage { .. }, _, _) => self.atmel_program(offset, data),
+            (BankSwitch, 0, _) => {
+                self.state = Ready;
+                self.bank = data as usize & 1;
+            }
+
+            (Ready, 0x5555, 0xAA) => self.state = Unlock1,
+            (Unlock1, 0x2AAA, 0x55) => self.state = Unlock2,
+            (Unlock2, 0x5555, _) => self.command(data),
+            // sector erase is the only command that doesn't go to 0x5555
+            (Unlock2, _, 0x30) if self.erase_ready => {
+                self.state = Ready;
+                self.erase_ready = false;
+                let sector = self.bank * BANK_SIZE + (offset & !(SECTOR_SIZE - 1));
+                self.flash[sector..sector + SECTOR_SIZE].fill(0xFF);
+                self.writes += 1;
+            }
+            // 0xF0 on its own also gets the chip out of ID mode
+            (_, _, 0xF0) => {
+                self.state = Ready;
+                self.id_mode = false;
+            }
+            _ => self.state = Ready,
+        }
+    }
+    fn save_type(&self) -> SaveType {
+        match self.chip.is_128kb() {
+            true => SaveType::Flash128K,
+            false => SaveType::Flash64K,
+        }
+    }
+
+    fn save_data(&self) -> &[u8] { &self.flash }
+    /// a 128K save means the ROM was detected wrong, so the chip gets swapped for a bigger one
+    fn load_save_data(&mut self, data: &[u8]) {
+        if data.len() == BANK_SIZE * 2 && !self.chip.is_128kb() {
+            self.chip = FlashChip::Sanyo;
+            self.flash = vec![0xFF; BANK_SIZE * 2];
+        }
+        load_flat_save(&mut self.flash, data);
+    }
+    fn save_writes(&self) -> u64 { self.writes }
+}
@@ -0,0 +1,145 @@
+use crate::mem::carts::rtc::Rtc;
+use crate::mem::carts::{CartHardware, Cartridge, Gyro, Rumble, SaveType, SolarSensor, TiltCart, TimeSource};
+
+const GPIO_DATA: u32 = 0x80000C4;
+const GPIO_DIRECTION: u32 = 0x80000C6;
+const GPIO_CONTROL: u32 = 0x80000C8;
+
+/// the 4 pin port some carts have in the middle of the ROM header,
+/// whatever extra hardware the cart has hangs off of it
+pub struct Gpio {
+    // what the GBA last wrote to the pins
+    data: u8,
+    // 1 is an output from the GBA, 0 is an input
+    direction: u8,
+    // when this is off the registers can't be read and the ROM shows through
+    readable: bool,
+
+    pub rtc: Option<Rtc>,
+    pub solar: Option<SolarSensor>,
+    pub rumble: Option<Rumble>,
+    pub gyro: Option<Gyro>,
+}
+impl Gpio {
+    pub fn new(hardware: &CartHardware) -> Self {
+        Self {
+            data: 0,
+            direction: 0,
+            readable: false,
+            rtc: hardware.rtc.then(|| Rtc::new(TimeSource::Host)),
+            solar: hardware.solar.then(SolarSensor::new),
+            rumble: hardware.rumble.then(Rumble::new),
+            gyro: hardware.gyro.then(Gyro::new),
+        }
+    }
+
+    /// what everything on the port is sending back to the GBA
+    fn pins(&self) -> u8 {
+        let mut pins = 0;
+        if let Some(rtc) = &self.rtc {
+            pins |= (rtc.sio as u8) << 1;
+        }
+        if let Some(gyro) = &self.gyro {
+            pins |= (gyro.output as u8) << 2;
+        }
+        if let Some(solar) = &self.solar {
+            pins |= (solar.output as u8) << 3;
+        }
+        return pins;
+    }
+
+    fn read(&self, address: u32) -> Option<u8> {
+        if !self.readable {
+            return None;
+        }
+        match address {
+            GPIO_DATA => Some((self.data & self.direction) | (self.pins() & !self.direction)),
+            GPIO_DIRECTION => Some(self.direction),
+            GPIO_CONTROL => Some(self.readable as u8),
+            // the top halves of the registers
+            _ => Some(0),
+        }
+    }
+
+    fn write(&mut self, address: u32, data: u8) {
+        match address {
+            GPIO_DATA => {
+                self.data = data & 0xF;
+                let pins = self.data & self.direction;
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.update(pins);
+                }
+                if let Some(solar) = &mut self.solar {
+                    solar.update(pins);
+                }
+                if let Some(gyro) = &mut self.gyro {
+                    gyro.update(pins);
+                }
+                if let Some(rumble) = &mut self.rumble {
+                    rumble.update(pins, self.direction);
+                }
+            }
+            GPIO_DIRECTION => self.direction = data & 0xF,
+            GPIO_CONTROL => self.readable = data & 1 == 1,
+            _ => {}
+        }
+    }
+}
+
+/// puts a GPIO port in front of any other cart, everything
+/// apart from the port's 6 bytes goes straight through
+pub struct GpioCart {
+    cart: Box<dyn Cartridge>,
+    pub gpio: Gpio,
+}
+impl GpioCart {
+    pub fn new(cart: Box<dyn Cartridge>, gpio: Gpio) -> Self {
+        Self { cart, gpio }
+    }
+}
+fn is_gpio_address(address: u32) -> bool {
+    (GPIO_DATA..GPIO_CONTROL + 2).contains(&address)
+}
+impl Cartridge for GpioCart {
+    fn read(&mut self, address: u32) -> u8 {
+        if is_gpio_address(address) {
+            if let Some(data) = self.gpio.read(address) {
+                return data;
+            }
+        }
+        self.cart.read(address)
+    }
+    fn peek(&self, address: u32) -> u8 {
+        if is_gpio_address(address) {
+            if let Some(data) = self.gpio.read(address) {
+                return data;
+            }
+        }
+        self.cart.peek(address)
+    }
+    fn write(&mut self, address: u32, data: u8, is_8_bit: bool) {
+        if is_gpio_address(address) {
+            self.gpio.write(address, data);
+            return;
+        }
+        self.cart.write(address, data, is_8_bit);
+    }
+    fn save_type(&self) -> SaveType { self.cart.save_type() }
+
+    fn dma_started(&mut self, destination: u32, amount: u32) {
+        self.cart.dma_started(destination, amount);
+    }
+    fn save_data(&self) -> &[u8] { self.cart.save_data() }
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.cart.load_save_data(data);
+    }
+    fn save_writes(&self) -> u64 {
+        let clock_sets = self.gpio.rtc.as_ref().map_or(0, Rtc::clock_sets);
+        self.cart.save_writes() + clock_sets
+    }
+    fn rtc_offset(&self) -> Option<i64> {
+        self.gpio.rtc.as_ref().map(Rtc::game_offset)
+    }
+    fn gpio(&mut self) -> Option<&mut Gpio> { Some(&mut self.gpio) }
+    fn tilt(&mut self) -> Option<&mut TiltCart> { self.cart.tilt() }
+}
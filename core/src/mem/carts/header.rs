@@ -0,0 +1,127 @@
+use std::fmt;
+
+// everything up to and including the two reserved bytes after the complement check
+pub const HEADER_SIZE: usize = 0xC0;
+// the BIOS refuses to boot anything where this doesn't match what it has
+const NINTENDO_LOGO: [u8; 156] = [
+    0x24, 0xFF, 0xAE, 0x51, 0x69, 0x9A, 0xA2, 0x21, 0x3D, 0x84, 0x82, 0x0A, 0x84, 0xE4, 0x09, 0xAD,
+    0x11, 0x24, 0x8B, 0x98, 0xC0, 0x81, 0x7F, 0x21, 0xA3, 0x52, 0xBE, 0x19, 0x93, 0x09, 0xCE, 0x20,
+    0x10, 0x46, 0x4A, 0x4A, 0xF8, 0x27, 0x31, 0xEC, 0x58, 0xC7, 0xE8, 0x33, 0x82, 0xE3, 0xCE, 0xBF,
+    0x85, 0xF4, 0xDF, 0x94, 0xCE, 0x4B, 0x09, 0xC1, 0x94, 0x56, 0x8A, 0xC0, 0x13, 0x72, 0xA7, 0xFC,
+    0x9F, 0x84, 0x4D, 0x73, 0xA3, 0xCA, 0x9A, 0x61, 0x58, 0x97, 0xA3, 0x27, 0xFC, 0x03, 0x98, 0x76,
+    0x23, 0x1D, 0xC7, 0x61, 0x03, 0x04, 0xAE, 0x56, 0xBF, 0x38, 0x84, 0x00, 0x40, 0xA7, 0x0E, 0xFD,
+    0xFF, 0x52, 0xFE, 0x03, 0x6F, 0x95, 0x30, 0xF1, 0x97, 0xFB, 0xC0, 0x85, 0x60, 0xD6, 0x80, 0x25,
+    0xA9, 0x63, 0xBE, 0x03, 0x01, 0x4E, 0x38, 0xE2, 0xF9, 0xA2, 0x34, 0xFF, 0xBB, 0x3E, 0x03, 0x44,
+    0x78, 0x00, 0x90, 0xCB, 0x88, 0x11, 0x3A, 0x94, 0x65, 0xC0, 0x7C, 0x63, 0x87, 0xF0, 0x3C, 0xAF,
+    0xD6, 0x25, 0xE4, 0x8B, 0x38, 0x0A, 0xAC, 0x72, 0x21, 0xD4, 0xF8, 0x07,
+];
+const FIXED_VALUE: u8 = 0x96;
+
+/// things which are wrong with the header, none of them stop the
+/// emulator from running the ROM but the real BIOS would refuse some
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderWarning {
+    // the ROM is smaller than the header, everything missing is read as 0
+    TooShort,
+    NoEntryBranch,
+    BadLogo,
+    // 0xB2 should always be 0x96
+    BadFixedValue(u8),
+    BadComplement { expected: u8, found: u8 },
+}
+impl fmt::Display for HeaderWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderWarning::TooShort => write!(f, "the ROM is smaller than its header"),
+            HeaderWarning::NoEntryBranch => write!(f, "the first instruction isn't a branch"),
+            HeaderWarning::BadLogo => write!(f, "the Nintendo logo doesn't match"),
+            HeaderWarning::BadFixedValue(value) => write!(f, "the fixed value is {value:X} instead of 96"),
+            HeaderWarning::BadComplement { expected, found } => {
+                write!(f, "the complement check is {found:X} but should be {expected:X}")
+            }
+        }
+    }
+}
+
+/// the first 0xC0 bytes of every ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomHeader {
+    // where the branch at 0x8000000 goes to, if it is one
+    pub entry_point: Option<u32>,
+    pub logo_valid: bool,
+    // up to 12 uppercase ascii characters
+    pub title: String,
+    // 4 characters, the last one is the region (E, J, P...)
+    pub game_code: String,
+    pub maker_code: String,
+    pub unit_code: u8,
+    pub device_type: u8,
+    pub version: u8,
+    pub complement: u8,
+    pub warnings: Vec<HeaderWarning>,
+}
+impl RomHeader {
+    pub fn parse(rom: &[u8]) -> Self {
+        let mut warnings = Vec::new();
+        let mut header = [0; HEADER_SIZE];
+        let length = rom.len().min(HEADER_SIZE);
+        header[..length].copy_from_slice(&rom[..length]);
+        if length < HEADER_SIZE {
+            warnings.push(HeaderWarning::TooShort);
+        }
+
+        // always an ARM `b`, the offset is in words from 8 bytes ahead
+        let branch = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let entry_point = match branch >> 24 == 0xEA {
+            true => Some(0x8000008u32.wrapping_add((((branch << 8) as i32) >> 6) as u32)),
+            false => None,
+        };
+        if entry_point.is_none() {
+            warnings.push(HeaderWarning::NoEntryBranch);
+        }
+
+        let logo_valid = header[0x4..0xA0] == NINTENDO_LOGO;
+        if !logo_valid {
+            warnings.push(HeaderWarning::BadLogo);
+        }
+        if header[0xB2] != FIXED_VALUE {
+            warnings.push(HeaderWarning::BadFixedValue(header[0xB2]));
+        }
+
+        let expected = complement_check(&header);
+        let complement = header[0xBD];
+        if complement != expected {
+            warnings.push(HeaderWarning::BadComplement { expected, found: complement });
+        }
+
+        Self {
+            entry_point,
+            logo_valid,
+            title: ascii_string(&header[0xA0..0xAC]),
+            game_code: ascii_string(&header[0xAC..0xB0]),
+            maker_code: ascii_string(&header[0xB0..0xB2]),
+            unit_code: header[0xB3],
+            device_type: header[0xB4],
+            version: header[0xBC],
+            complement,
+            warnings,
+        }
+    }
+}
+
+/// the byte the BIOS expects at 0xBD, from everything between the title and itself
+fn complement_check(header: &[u8]) -> u8 {
+    let sum = header[0xA0..0xBD].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    return 0u8.wrapping_sub(sum).wrapping_sub(0x19);
+}
+
+// unused characters are padded with 0, anything that isn't printable gets dropped
+fn ascii_string(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|b| **b != 0)
+        .filter(|b| b.is_ascii_graphic() || **b == b' ')
+        .map(|b| *b as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
@@ -0,0 +1,156 @@
+use std::{fmt, fs, io::{self, Read}, path::Path};
+use flate2::{read::{DeflateDecoder, MultiGzDecoder}, Crc};
+use crate::mem::carts::{PatchError, MAX_ROM_SIZE};
+
+const ZIP_LOCAL_HEADER: u32 = 0x04034B50;
+const ZIP_CENTRAL_HEADER: u32 = 0x02014B50;
+const ZIP_END_OF_DIRECTORY: u32 = 0x06054B50;
+// the end of directory record can have a comment of up to this many bytes after it
+const ZIP_MAX_COMMENT: usize = 0xFFFF;
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+// the first entry in a zip with one of these is taken as the ROM
+const ROM_EXTENSIONS: [&str; 3] = [".gba", ".agb", ".bin"];
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Empty,
+    // multiboot images have to fit in EWRAM, and a ROM in the cart's 32MB
+    TooLarge { size: usize, max: usize },
+    // the zip didn't have anything that looks like a ROM in it
+    NoRomInArchive,
+    BadArchive(&'static str),
+    // only stored and deflated zip entries are supported
+    UnsupportedCompression(u16),
+    BadChecksum { expected: u32, found: u32 },
+    Patch(PatchError),
+    BadElf(&'static str),
+}
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{e}"),
+            LoadError::Empty => write!(f, "the ROM is empty"),
+            LoadError::TooLarge { size, max } => write!(f, "it is {size:X} bytes but can't be more than {max:X}"),
+            LoadError::NoRomInArchive => write!(f, "there is no .gba, .agb or .bin file in the zip"),
+            LoadError::BadArchive(reason) => write!(f, "the zip is broken, {reason}"),
+            LoadError::UnsupportedCompression(method) => write!(f, "zip compression method {method} isn't supported"),
+            LoadError::BadChecksum { expected, found } => {
+                write!(f, "the CRC32 is {found:08X} but should be {expected:08X}")
+            }
+            LoadError::Patch(e) => write!(f, "couldn't apply the patch, {e}"),
+            LoadError::BadElf(reason) => write!(f, "the ELF can't be loaded, {reason}"),
+        }
+    }
+}
+impl std::error::Error for LoadError {}
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+impl From<PatchError> for LoadError {
+    fn from(e: PatchError) -> Self {
+        LoadError::Patch(e)
+    }
+}
+
+/// reads a ROM image, unpacking it first if it is a .zip or .gz. This goes off what is
+/// in the file rather than its name, so anything else is taken as a raw ROM
+pub fn load_rom(path: &Path) -> Result<Vec<u8>, LoadError> {
+    let file = fs::read(path)?;
+    let rom = if file.starts_with(&ZIP_LOCAL_HEADER.to_le_bytes()) {
+        unzip_rom(&file)?
+    } else if file.starts_with(&GZIP_MAGIC) {
+        let mut rom = Vec::new();
+        MultiGzDecoder::new(&file[..]).read_to_end(&mut rom)?;
+        rom
+    } else {
+        file
+    };
+
+    if rom.is_empty() {
+        return Err(LoadError::Empty);
+    }
+    return Ok(rom);
+}
+
+/// anything read out of the zip can be garbage, so adding to it might not fit
+fn add(offset: usize, length: usize) -> Result<usize, LoadError> {
+    offset.checked_add(length).ok_or(LoadError::BadArchive("an offset in it is too big"))
+}
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, LoadError> {
+    match data.get(offset..add(offset, 2)?) {
+        Some(bytes) => Ok(u16::from_le_bytes([bytes[0], bytes[1]])),
+        None => Err(LoadError::BadArchive("it ends too early")),
+    }
+}
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, LoadError> {
+    match data.get(offset..add(offset, 4)?) {
+        Some(bytes) => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => Err(LoadError::BadArchive("it ends too early")),
+    }
+}
+
+/// the central directory at the end of the zip says where everything is,
+/// the local headers before each file can't be trusted for the sizes
+fn unzip_rom(zip: &[u8]) -> Result<Vec<u8>, LoadError> {
+    let search_start = zip.len().saturating_sub(22 + ZIP_MAX_COMMENT);
+    let end = (search_start..zip.len().saturating_sub(21))
+        .rev()
+        .find(|offset| read_u32(zip, *offset).ok() == Some(ZIP_END_OF_DIRECTORY))
+        .ok_or(LoadError::BadArchive("there is no end of central directory"))?;
+
+    let entries = read_u16(zip, end + 10)?;
+    let mut offset = read_u32(zip, end + 16)? as usize;
+    for _ in 0..entries {
+        if read_u32(zip, offset)? != ZIP_CENTRAL_HEADER {
+            return Err(LoadError::BadArchive("a central directory entry is missing"));
+        }
+        let method = read_u16(zip, offset + 10)?;
+        let crc = read_u32(zip, offset + 16)?;
+        let compressed_size = read_u32(zip, offset + 20)? as usize;
+        let size = read_u32(zip, offset + 24)? as usize;
+        let name_length = read_u16(zip, offset + 28)? as usize;
+        let extra_length = read_u16(zip, offset + 30)? as usize;
+        let comment_length = read_u16(zip, offset + 32)? as usize;
+        let local_header = read_u32(zip, offset + 42)? as usize;
+        let name_start = add(offset, 46)?;
+        let name = zip.get(name_start..add(name_start, name_length)?)
+            .ok_or(LoadError::BadArchive("it ends too early"))?;
+        offset = add(name_start, name_length + extra_length + comment_length)?;
+
+        let name = String::from_utf8_lossy(name).to_lowercase();
+        if !ROM_EXTENSIONS.iter().any(|extension| name.ends_with(extension)) {
+            continue;
+        }
+
+        if read_u32(zip, local_header)? != ZIP_LOCAL_HEADER {
+            return Err(LoadError::BadArchive("a local file header is missing"));
+        }
+        let data_start = add(local_header, 30)?;
+        let data_start = add(data_start, read_u16(zip, local_header + 26)? as usize)?;
+        let data_start = add(data_start, read_u16(zip, local_header + 28)? as usize)?;
+        let data = zip.get(data_start..add(data_start, compressed_size)?)
+            .ok_or(LoadError::BadArchive("it ends too early"))?;
+        if size > MAX_ROM_SIZE {
+            return Err(LoadError::TooLarge { size, max: MAX_ROM_SIZE });
+        }
+
+        // the size can't be trusted either, anything past it is caught by the CRC32
+        let mut rom = Vec::new();
+        match method {
+            0 => rom.extend_from_slice(data),
+            8 => { DeflateDecoder::new(data).take(size as u64 + 1).read_to_end(&mut rom)?; }
+            _ => return Err(LoadError::UnsupportedCompression(method)),
+        }
+
+        let mut found = Crc::new();
+        found.update(&rom);
+        if found.sum() != crc {
+            return Err(LoadError::BadChecksum { expected: crc, found: found.sum() });
+        }
+        return Ok(rom);
+    }
+    return Err(LoadError::NoRomInArchive);
+}
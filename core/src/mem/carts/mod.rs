@@ -0,0 +1,325 @@
+mod flash;
+mod eeprom;
+mod gpio;
+mod rtc;
+mod peripherals;
+mod header;
+mod loader;
+mod patch;
+
+use std::{fs, path::Path};
+pub use flash::{FlashChip, FlashRom};
+pub use eeprom::EepRom;
+pub use gpio::{Gpio, GpioCart};
+pub use rtc::{Rtc, TimeSource};
+pub use loader::{load_rom, LoadError};
+pub use patch::{apply_patch, find_patch, PatchError};
+pub use header::{HeaderWarning, RomHeader, HEADER_SIZE};
+pub use peripherals::{Gyro, Rumble, RumbleCallback, SolarSensor, TiltCart};
+use crate::mem::split_memory_address;
+
+// the cart only has 32MB of address space for the ROM
+const MAX_ROM_SIZE: usize = 0x2000000;
+
+/// everything from 0x8000000 up to 0xFFFFFFF goes through here, so the ROM
+/// as well as whatever the cart uses for saving (and anything else on it)
+pub trait Cartridge: Send + Sync {
+    /// what the CPU or DMA sees, this can change the cart's state
+    /// (the EEPROM moves onto its next bit for example)
+    fn read(&mut self, address: u32) -> u8;
+    /// the same as `read` but without any side effects, for the debugger and anything
+    /// else looking at the cart without a real access. Opcode fetches go through `read`
+    fn peek(&self, address: u32) -> u8;
+    fn write(&mut self, address: u32, data: u8, is_8_bit: bool);
+    fn save_type(&self) -> SaveType;
+
+    /// DMA3 is the only way games talk to the EEPROM, and how long the first transfer
+    /// is gives away its size. Nothing else needs to know about it
+    fn dma_started(&mut self, _destination: u32, _amount: u32) {}
+
+    /// the backup memory exactly as it would be in a .sav file,
+    /// empty if there is nothing worth saving
+    fn save_data(&self) -> &[u8] { &[] }
+    /// the opposite of `save_data`, anything that doesn't fit is ignored
+    fn load_save_data(&mut self, _data: &[u8]) {}
+    /// goes up every time the game changes the backup memory, so
+    /// whoever is saving it can tell when it needs to
+    fn save_writes(&self) -> u64 { 0 }
+    /// how far the game moved the RTC from the time source, for carts which have one
+    fn rtc_offset(&self) -> Option<i64> { None }
+
+    /// only carts with extra hardware (like an RTC) have one of these
+    fn gpio(&mut self) -> Option<&mut Gpio> { None }
+    /// the tilt sensor isn't on the GPIO port, so it has its own way in
+    fn tilt(&mut self) -> Option<&mut TiltCart> { None }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaveType {
+    None,
+    Sram,
+    // the size isn't in the ID string, it only shows up once the game starts using it
+    Eeprom,
+    Flash64K,
+    Flash128K,
+}
+
+// Nintendo's libraries leave these in the ROM, always word aligned
+const SAVE_TYPE_IDS: [(&[u8], SaveType); 6] = [
+    (b"EEPROM_V", SaveType::Eeprom),
+    (b"SRAM_V", SaveType::Sram),
+    (b"SRAM_F_V", SaveType::Sram),
+    (b"FLASH_V", SaveType::Flash64K),
+    (b"FLASH512_V", SaveType::Flash64K),
+    (b"FLASH1M_V", SaveType::Flash128K),
+];
+
+// games where the ID string is missing or wrong, keyed by the game code at 0xAC
+const SAVE_TYPE_OVERRIDES: [(&[u8; 4], SaveType); 11] = [
+    (b"AI2E", SaveType::None), // Iridion II
+    (b"AI2P", SaveType::None),
+    (b"A2YE", SaveType::None), // Top Gun - Combat Zones
+    (b"ALFE", SaveType::Eeprom), // Dragon Ball Z - The Legacy of Goku II
+    (b"ALFP", SaveType::Eeprom),
+    (b"AXVE", SaveType::Flash128K), // Pokemon Ruby
+    (b"AXPE", SaveType::Flash128K), // Pokemon Sapphire
+    (b"BPEE", SaveType::Flash128K), // Pokemon Emerald
+    (b"BPRE", SaveType::Flash128K), // Pokemon FireRed
+    (b"BPGE", SaveType::Flash128K), // Pokemon LeafGreen
+    (b"U3IE", SaveType::Eeprom), // Boktai
+];
+
+// Nintendo's RTC library leaves this behind like the save type IDs
+const RTC_ID: &[u8] = b"SIIRTC_V";
+
+/// anything on the cart apart from the ROM and the backup memory
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CartHardware {
+    pub rtc: bool,
+    pub solar: bool,
+    pub rumble: bool,
+    pub gyro: bool,
+    pub tilt: bool,
+}
+const NO_HARDWARE: CartHardware = CartHardware {
+    rtc: false,
+    solar: false,
+    rumble: false,
+    gyro: false,
+    tilt: false,
+};
+const RTC: CartHardware = CartHardware { rtc: true, ..NO_HARDWARE };
+const RTC_SOLAR: CartHardware = CartHardware { rtc: true, solar: true, ..NO_HARDWARE };
+const RUMBLE: CartHardware = CartHardware { rumble: true, ..NO_HARDWARE };
+const GYRO_RUMBLE: CartHardware = CartHardware { gyro: true, rumble: true, ..NO_HARDWARE };
+const TILT: CartHardware = CartHardware { tilt: true, ..NO_HARDWARE };
+
+// none of this is in the ROM anywhere (the RTC sometimes is), so it goes by game code
+const CART_HARDWARE: [(&[u8; 4], CartHardware); 24] = [
+    (b"AXVE", RTC), // Pokemon Ruby
+    (b"AXPE", RTC), // Pokemon Sapphire
+    (b"AXVJ", RTC),
+    (b"AXPJ", RTC),
+    (b"BPEE", RTC), // Pokemon Emerald
+    (b"BPEJ", RTC),
+    (b"U3IE", RTC_SOLAR), // Boktai
+    (b"U3IJ", RTC_SOLAR),
+    (b"U3IP", RTC_SOLAR),
+    (b"U32E", RTC_SOLAR), // Boktai 2
+    (b"U32J", RTC_SOLAR),
+    (b"U32P", RTC_SOLAR),
+    (b"U33J", RTC_SOLAR), // Boktai 3
+    (b"V49E", RUMBLE), // Drill Dozer
+    (b"V49J", RUMBLE),
+    (b"V49P", RUMBLE),
+    (b"RZWE", GYRO_RUMBLE), // WarioWare Twisted
+    (b"RZWJ", GYRO_RUMBLE),
+    (b"RZWP", GYRO_RUMBLE),
+    (b"KYGE", TILT), // Yoshi Topsy-Turvy
+    (b"KYGJ", TILT),
+    (b"KYGP", TILT),
+    (b"KHPJ", TILT), // Koro Koro Puzzle
+    (b"KHPE", TILT),
+];
+
+fn game_code(rom: &[u8]) -> Option<&[u8]> {
+    rom.get(0xAC..0xB0)
+}
+
+/// the override table wins, otherwise the first ID string found is used.
+/// Homebrew doesn't usually have one but still expects SRAM to be there
+pub fn detect_save_type(rom: &[u8]) -> SaveType {
+    if let Some(game_code) = game_code(rom) {
+        for (code, save_type) in SAVE_TYPE_OVERRIDES {
+            if game_code == code {
+                return save_type;
+            }
+        }
+    }
+
+    for offset in (0..rom.len()).step_by(4) {
+        for (id, save_type) in SAVE_TYPE_IDS {
+            if rom[offset..].starts_with(id) {
+                return save_type;
+            }
+        }
+    }
+    return SaveType::Sram;
+}
+
+pub fn detect_hardware(rom: &[u8]) -> CartHardware {
+    let mut hardware = NO_HARDWARE;
+    if let Some(game_code) = game_code(rom) {
+        for (code, game_hardware) in CART_HARDWARE {
+            if game_code == code {
+                hardware = game_hardware;
+            }
+        }
+    }
+    if !hardware.rtc {
+        hardware.rtc = (0..rom.len()).step_by(4).any(|offset| rom[offset..].starts_with(RTC_ID));
+    }
+    return hardware;
+}
+
+/// reads the ROM from `path` (unpacking it if it needs to be) and
+/// gives it whichever backup memory and extra hardware it was made for.
+/// Without a `patch` one with the same name as the ROM is used, if it's there
+pub fn load_cartridge(path: &Path, patch: Option<&Path>) -> Result<Box<dyn Cartridge>, LoadError> {
+    let mut rom = load_rom(path)?;
+    let patch = patch.map(|p| p.to_path_buf()).or_else(|| find_patch(path));
+    if let Some(patch) = patch {
+        rom = apply_patch(&rom, &fs::read(patch)?)?;
+    }
+    if rom.is_empty() {
+        return Err(LoadError::Empty);
+    }
+    return Ok(cartridge_from_rom(rom));
+}
+
+/// the same as `load_cartridge` for a ROM which is already in memory
+pub fn cartridge_from_rom(rom: Vec<u8>) -> Box<dyn Cartridge> {
+    let hardware = detect_hardware(&rom);
+    let mut cart: Box<dyn Cartridge> = match detect_save_type(&rom) {
+        SaveType::None => Box::new(RomOnly { rom }),
+        SaveType::Sram => Box::new(Sram::new(rom)),
+        SaveType::Eeprom => Box::new(EepRom::new(rom)),
+        SaveType::Flash64K => Box::new(FlashRom::new(rom, FlashChip::Panasonic)),
+        SaveType::Flash128K => Box::new(FlashRom::new(rom, FlashChip::Sanyo)),
+    };
+    if hardware.tilt {
+        cart = Box::new(TiltCart::new(cart));
+    }
+    if hardware.rtc || hardware.solar || hardware.rumble || hardware.gyro {
+        cart = Box::new(GpioCart::new(cart, Gpio::new(&hardware)));
+    }
+    return cart;
+}
+
+/// 0x8000000, 0xA000000 and 0xC000000 are all mirrors of the same 32MB
+fn read_rom(rom: &[u8], address: u32) -> u8 {
+    let (upp, low) = split_memory_address(address);
+    if upp % 2 == 1 {
+        return rom[(low + 0x1000000) % rom.len()];
+    }
+    return rom[low % rom.len()];
+}
+
+/// nothing is connected to the save area, reading it just gives 0xFF
+pub struct RomOnly {
+    rom: Vec<u8>,
+}
+impl RomOnly {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Self { rom }
+    }
+}
+impl Cartridge for RomOnly {
+    fn read(&mut self, address: u32) -> u8 {
+        self.peek(address)
+    }
+    fn peek(&self, address: u32) -> u8 {
+        let (upp, _) = split_memory_address(address);
+        match upp {
+            0x8..=0xD => read_rom(&self.rom, address),
+            _ => 0xFF,
+        }
+    }
+    fn write(&mut self, _address: u32, _data: u8, _is_8_bit: bool) {}
+    fn save_type(&self) -> SaveType { SaveType::None }
+}
+
+/// nothing plugged in, like when something was sent over by multiboot. The cart
+/// bus keeps the last address it was given so ROM reads see the address halved
+pub struct EmptySlot;
+impl Cartridge for EmptySlot {
+    fn read(&mut self, address: u32) -> u8 {
+        self.peek(address)
+    }
+    fn peek(&self, address: u32) -> u8 {
+        let (upp, _) = split_memory_address(address);
+        match upp {
+            0x8..=0xD => {
+                let halfword = (address >> 1) & 0xFFFF;
+                (halfword >> ((address & 1) * 8)) as u8
+            }
+            _ => 0xFF,
+        }
+    }
+    fn write(&mut self, _address: u32, _data: u8, _is_8_bit: bool) {}
+    fn save_type(&self) -> SaveType { SaveType::None }
+}
+
+/// copies as much of `data` as will fit, for carts where the save is just flat memory
+fn load_flat_save(memory: &mut [u8], data: &[u8]) {
+    let length = memory.len().min(data.len());
+    memory[..length].copy_from_slice(&data[..length]);
+}
+
+// every SRAM cart has 32KB, it's mirrored across the rest of 0xE000000
+const SRAM_SIZE: usize = 0x8000;
+// this is handled the same as I am already doing it
+// its just the SRAM that is different, given its name and all
+pub struct Sram {
+    rom: Vec<u8>,
+    sram: Vec<u8>,
+    writes: u64,
+}
+impl Sram {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Self {
+            rom,
+            sram: vec![0; SRAM_SIZE],
+            writes: 0,
+        }
+    }
+}
+impl Cartridge for Sram {
+    fn read(&mut self, address: u32) -> u8 {
+        self.peek(address)
+    }
+    fn peek(&self, address: u32) -> u8 {
+        let (upp, low) = split_memory_address(address);
+        match upp {
+            0x8..=0xD => read_rom(&self.rom, address),
+            _ => self.sram[low % self.sram.len()],
+        }
+    }
+    fn write(&mut self, address: u32, data: u8, _is_8_bit: bool) {
+        let (upp, low) = split_memory_address(address);
+        if upp < 0xE {
+            println!("cannot write to ROM {address:X}, {data:X}");
+            return;
+        }
+        let length = self.sram.len();
+        self.sram[low % length] = data;
+        self.writes += 1;
+    }
+    fn save_type(&self) -> SaveType { SaveType::Sram }
+
+    fn save_data(&self) -> &[u8] { &self.sram }
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_flat_save(&mut self.sram, data);
+    }
+    fn save_writes(&self) -> u64 { self.writes }
+}
@@ -0,0 +1,442 @@
+use std::{fmt, path::{Path, PathBuf}};
+use flate2::Crc;
+use super::MAX_ROM_SIZE;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: usize = 0x454F46;
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+// UPS and BPS both end with the source, target and patch CRC32s
+const FOOTER_SIZE: usize = 12;
+// the order a same-named patch is looked for next to the ROM
+const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatchError {
+    UnknownFormat,
+    // the patch ran out before it said it would
+    Truncated,
+    // the CRC32 of the patch itself, the ROM it was made for, or what applying it gave
+    BadPatchChecksum { expected: u32, found: u32 },
+    WrongSource { expected: u32, found: u32 },
+    BadTargetChecksum { expected: u32, found: u32 },
+    // a BPS copy reached outside of the ROM, or wrote past the end of the new one
+    OutOfBounds,
+    // a UPS or BPS number that doesn't fit in a usize
+    BadNumber,
+    // the patched ROM would be bigger than a cart can hold
+    TooLarge(usize),
+}
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "the patch isn't IPS, UPS or BPS"),
+            PatchError::Truncated => write!(f, "the patch ends too early"),
+            PatchError::BadPatchChecksum { expected, found } => {
+                write!(f, "the patch's CRC32 is {found:08X} but should be {expected:08X}, it is probably corrupt")
+            }
+            PatchError::WrongSource { expected, found } => {
+                write!(f, "the ROM's CRC32 is {found:08X} but the patch was made for {expected:08X}")
+            }
+            PatchError::BadTargetChecksum { expected, found } => {
+                write!(f, "the patched ROM's CRC32 is {found:08X} but should be {expected:08X}")
+            }
+            PatchError::OutOfBounds => write!(f, "the patch reaches outside of the ROM"),
+            PatchError::BadNumber => write!(f, "the patch has a number in it which is too big"),
+            PatchError::TooLarge(size) => {
+                write!(f, "the patched ROM would be {size:X} bytes but can't be more than {MAX_ROM_SIZE:X}")
+            }
+        }
+    }
+}
+
+/// a .ips, .ups or .bps with the same name as the ROM, if there is one
+pub fn find_patch(rom_path: &Path) -> Option<PathBuf> {
+    PATCH_EXTENSIONS.iter()
+        .map(|extension| rom_path.with_extension(extension))
+        .find(|path| path.exists())
+}
+
+/// the format is worked out from the patch's header rather than its name
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        return apply_ips(rom, patch);
+    }
+    if patch.starts_with(UPS_MAGIC) {
+        return apply_ups(rom, patch);
+    }
+    if patch.starts_with(BPS_MAGIC) {
+        return apply_bps(rom, patch);
+    }
+    return Err(PatchError::UnknownFormat);
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    return crc.sum();
+}
+
+/// keeps track of where the patch is up to, everything fails with `Truncated`
+struct PatchReader<'a> {
+    patch: &'a [u8],
+    position: usize,
+}
+impl<'a> PatchReader<'a> {
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        let byte = *self.patch.get(self.position).ok_or(PatchError::Truncated)?;
+        self.position += 1;
+        return Ok(byte);
+    }
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.position.checked_add(length).ok_or(PatchError::Truncated)?;
+        let bytes = self.patch.get(self.position..end).ok_or(PatchError::Truncated)?;
+        self.position += length;
+        return Ok(bytes);
+    }
+    fn big_endian(&mut self, length: usize) -> Result<usize, PatchError> {
+        let bytes = self.bytes(length)?;
+        return Ok(bytes.iter().fold(0, |value, b| (value << 8) | *b as usize));
+    }
+    /// UPS and BPS numbers are 7 bits at a time, lowest first, with the top bit
+    /// ending it. Each continuation adds one more so nothing has two encodings
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()? as usize;
+            let bits = (byte & 0x7F).checked_mul(shift).ok_or(PatchError::BadNumber)?;
+            value = value.checked_add(bits).ok_or(PatchError::BadNumber)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::BadNumber)?;
+            value = value.checked_add(shift).ok_or(PatchError::BadNumber)?;
+        }
+    }
+    /// the size the patched ROM will be, checked before anything gets allocated for it
+    fn target_size(&mut self) -> Result<usize, PatchError> {
+        let size = self.number()?;
+        if size > MAX_ROM_SIZE {
+            return Err(PatchError::TooLarge(size));
+        }
+        return Ok(size);
+    }
+}
+
+/// records of a 3 byte offset and 2 byte length (both big endian) followed
+/// by the data, a length of 0 is a run of one byte instead
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = rom.to_vec();
+    let mut reader = PatchReader { patch, position: IPS_MAGIC.len() };
+    loop {
+        let offset = reader.big_endian(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+
+        let length = reader.big_endian(2)?;
+        let (length, data) = match length {
+            0 => {
+                let length = reader.big_endian(2)?;
+                (length, vec![reader.byte()?; length])
+            }
+            _ => (length, reader.bytes(length)?.to_vec()),
+        };
+        if target.len() < offset + length {
+            target.resize(offset + length, 0);
+        }
+        target[offset..offset + length].copy_from_slice(&data);
+    }
+
+    // some patches shrink the ROM back down afterwards
+    if let Ok(size) = reader.big_endian(3) {
+        target.truncate(size);
+    }
+    return Ok(target);
+}
+
+fn check_footer(rom: &[u8], patch: &[u8]) -> Result<(u32, u32), PatchError> {
+    if patch.len() < FOOTER_SIZE + 4 {
+        return Err(PatchError::Truncated);
+    }
+    let footer = &patch[patch.len() - FOOTER_SIZE..];
+    let read_crc = |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+    let (source_crc, target_crc, patch_crc) = (read_crc(0), read_crc(4), read_crc(8));
+
+    let found = crc32(&patch[..patch.len() - 4]);
+    if found != patch_crc {
+        return Err(PatchError::BadPatchChecksum { expected: patch_crc, found });
+    }
+    let found = crc32(rom);
+    if found != source_crc {
+        return Err(PatchError::WrongSource { expected: source_crc, found });
+    }
+    return Ok((source_crc, target_crc));
+}
+
+fn check_target(target: &[u8], expected: u32) -> Result<(), PatchError> {
+    let found = crc32(target);
+    if found != expected {
+        return Err(PatchError::BadTargetChecksum { expected, found });
+    }
+    return Ok(());
+}
+
+/// gaps to skip followed by bytes to XOR with the ROM, each run ends with a 0
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (_, target_crc) = check_footer(rom, patch)?;
+    let end = patch.len() - FOOTER_SIZE;
+    let mut reader = PatchReader { patch: &patch[..end], position: UPS_MAGIC.len() };
+
+    let _source_size = reader.number()?;
+    let target_size = reader.target_size()?;
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+
+    let mut position: usize = 0;
+    while reader.position < end {
+        position = position.checked_add(reader.number()?).ok_or(PatchError::BadNumber)?;
+        loop {
+            let byte = reader.byte()?;
+            if position < target_size {
+                target[position] ^= byte;
+            }
+            position += 1;
+            if byte == 0 {
+                break;
+            }
+        }
+    }
+
+    check_target(&target, target_crc)?;
+    return Ok(target);
+}
+
+/// builds the new ROM from the start using copies out of the old ROM,
+/// the patch, or what has already been written
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (_, target_crc) = check_footer(rom, patch)?;
+    let end = patch.len() - FOOTER_SIZE;
+    let mut reader = PatchReader { patch: &patch[..end], position: BPS_MAGIC.len() };
+
+    let _source_size = reader.number()?;
+    let target_size = reader.target_size()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    let mut target: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_offset: isize = 0;
+    let mut target_offset: isize = 0;
+    while reader.position < end {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        if length > target_size - target.len() {
+            return Err(PatchError::OutOfBounds);
+        }
+        match action & 3 {
+            // source read, the same bytes as the old ROM at the same place
+            0 => {
+                let start = target.len();
+                let data = rom.get(start..start + length).ok_or(PatchError::OutOfBounds)?;
+                target.extend_from_slice(data);
+            }
+            // target read, bytes straight from the patch
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            // source copy, from anywhere in the old ROM
+            2 => {
+                source_offset = offset_by(source_offset, reader.number()?)?;
+                let start = usize::try_from(source_offset).map_err(|_| PatchError::OutOfBounds)?;
+                let data = rom.get(start..start + length).ok_or(PatchError::OutOfBounds)?;
+                target.extend_from_slice(data);
+                source_offset += length as isize;
+            }
+            // target copy, one byte at a time since it can overlap what it is writing
+            _ => {
+                target_offset = offset_by(target_offset, reader.number()?)?;
+                for _ in 0..length {
+                    let index = usize::try_from(target_offset).map_err(|_| PatchError::OutOfBounds)?;
+                    let byte = *target.get(index).ok_or(PatchError::OutOfBounds)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    check_target(&target, target_crc)?;
+    return Ok(target);
+}
+
+// the bottom bit is the sign
+fn signed_number(number: usize) -> isize {
+    match number & 1 == 1 {
+        true => -((number >> 1) as isize),
+        false => (number >> 1) as isize,
+    }
+}
+fn offset_by(offset: isize, number: usize) -> Result<isize, PatchError> {
+    return offset.checked_add(signed_number(number)).ok_or(PatchError::OutOfBounds);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the opposite of `PatchReader::number`
+    fn number(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let low = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(0x80 | low);
+                return bytes;
+            }
+            bytes.push(low);
+            value -= 1;
+        }
+    }
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend(crc32(target).to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend(patch_crc.to_le_bytes());
+        return patch;
+    }
+
+    const ROM: &[u8] = &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+
+    #[test]
+    fn ips_records_and_runs() {
+        let mut patch = IPS_MAGIC.to_vec();
+        // 2 bytes at 1
+        patch.extend([0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        // a run of 3 0xCCs at 6, which grows the ROM
+        patch.extend([0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+        patch.extend(b"EOF");
+
+        let target = apply_patch(ROM, &patch).unwrap();
+        assert_eq!(target, [0x00, 0xAA, 0xBB, 0x33, 0x44, 0x55, 0xCC, 0xCC, 0xCC]);
+    }
+
+    #[test]
+    fn ips_truncate_after_eof() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend(b"EOF");
+        patch.extend([0x00, 0x00, 0x04]);
+        assert_eq!(apply_patch(ROM, &patch).unwrap(), &ROM[..4]);
+    }
+
+    #[test]
+    fn ips_truncated_record() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend([0x00, 0x00, 0x01, 0x00, 0x04, 0xAA]);
+        assert_eq!(apply_patch(ROM, &patch), Err(PatchError::Truncated));
+    }
+
+    fn ups_patch(target: &[u8]) -> Vec<u8> {
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(number(ROM.len()));
+        patch.extend(number(target.len()));
+        // skip 2, then xor 2 bytes
+        patch.extend(number(2));
+        patch.extend([0x22 ^ 0xAB, 0x33 ^ 0xCD, 0x00]);
+        return with_footer(patch, ROM, target);
+    }
+
+    #[test]
+    fn ups_xors_the_rom() {
+        let target = [0x00, 0x11, 0xAB, 0xCD, 0x44, 0x55, 0x66, 0x77];
+        assert_eq!(apply_patch(ROM, &ups_patch(&target)).unwrap(), target);
+    }
+
+    #[test]
+    fn ups_checksums() {
+        let target = [0x00, 0x11, 0xAB, 0xCD, 0x44, 0x55, 0x66, 0x77];
+        let patch = ups_patch(&target);
+
+        let other_rom = [0xFF; 8];
+        assert!(matches!(apply_patch(&other_rom, &patch), Err(PatchError::WrongSource { .. })));
+
+        let mut corrupt = patch.clone();
+        corrupt[6] ^= 1;
+        assert!(matches!(apply_patch(ROM, &corrupt), Err(PatchError::BadPatchChecksum { .. })));
+
+        // the patch itself is fine but it says the result should be something else
+        let wrong_target = ups_patch(&[0; 8]);
+        assert!(matches!(apply_patch(ROM, &wrong_target), Err(PatchError::BadTargetChecksum { .. })));
+
+        assert_eq!(apply_patch(ROM, &patch[..10]), Err(PatchError::Truncated));
+    }
+
+    #[test]
+    fn bps_actions() {
+        let target = [0x00, 0x11, 0xEE, 0x66, 0x77, 0x77, 0x77, 0x77];
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(number(ROM.len()));
+        patch.extend(number(target.len()));
+        patch.extend(number(0));
+        // source read of 2, the action is the bottom 2 bits and the length - 1 is above it
+        patch.extend(number(1 << 2));
+        // target read of 1
+        patch.extend(number(1));
+        patch.push(0xEE);
+        // source copy of 2 from 6
+        patch.extend(number((1 << 2) | 2));
+        patch.extend(number(6 << 1));
+        // target copy of 3 from 4, which overlaps what it writes
+        patch.extend(number((2 << 2) | 3));
+        patch.extend(number(4 << 1));
+        let patch = with_footer(patch, ROM, &target);
+
+        assert_eq!(apply_patch(ROM, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_copy_out_of_bounds() {
+        let target = [0x00; 2];
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(number(ROM.len()));
+        patch.extend(number(target.len()));
+        patch.extend(number(0));
+        patch.extend(number((1 << 2) | 2));
+        patch.extend(number(20 << 1));
+        let patch = with_footer(patch, ROM, &target);
+
+        assert_eq!(apply_patch(ROM, &patch), Err(PatchError::OutOfBounds));
+    }
+
+    #[test]
+    fn bad_sizes() {
+        // a number that never ends before it overflows
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend([0x7F; 12]);
+        patch.push(0x80);
+        let patch = with_footer(patch, ROM, ROM);
+        assert_eq!(apply_patch(ROM, &patch), Err(PatchError::BadNumber));
+
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(number(ROM.len()));
+        patch.extend(number(usize::MAX >> 8));
+        let patch = with_footer(patch, ROM, ROM);
+        assert_eq!(apply_patch(ROM, &patch), Err(PatchError::TooLarge(usize::MAX >> 8)));
+
+        // a target copy longer than the target
+        let target = [0x00; 2];
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(number(ROM.len()));
+        patch.extend(number(target.len()));
+        patch.extend(number(0));
+        patch.extend(number(1 << 2));
+        patch.extend(number((usize::MAX >> 3 << 2) | 3));
+        patch.extend(number(0));
+        let patch = with_footer(patch, ROM, &target);
+        assert_eq!(apply_patch(ROM, &patch), Err(PatchError::OutOfBounds));
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert_eq!(apply_patch(ROM, b"nothing"), Err(PatchError::UnknownFormat));
+    }
+}
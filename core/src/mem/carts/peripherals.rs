@@ -0,0 +1,208 @@
+use crate::mem::carts::{Cartridge, Gpio, SaveType};
+use crate::mem::split_memory_address;
+
+pub type RumbleCallback = Box<dyn FnMut(bool) + Send + Sync>;
+
+/// Boktai's light sensor, the game resets a counter (pin 1) and then clocks it
+/// (pin 0) until the sensor says it has gone past the light level (pin 3)
+pub struct SolarSensor {
+    // 0 is darkness and 0xFF is direct sunlight
+    pub light_level: u8,
+    counter: u8,
+    sample: u8,
+    clock: bool,
+    pub output: bool,
+}
+impl SolarSensor {
+    pub fn new() -> Self {
+        Self {
+            light_level: 0,
+            counter: 0,
+            sample: 0xFF,
+            clock: false,
+            output: false,
+        }
+    }
+
+    pub fn update(&mut self, pins: u8) {
+        // pin 2 low is the sensor being selected
+        if (pins >> 2) & 1 == 1 {
+            return;
+        }
+        if (pins >> 1) & 1 == 1 {
+            self.counter = 0;
+            self.sample = 0xFF - self.light_level;
+        }
+
+        let clock = pins & 1 == 1;
+        if clock && !self.clock {
+            self.counter = self.counter.wrapping_add(1);
+        }
+        self.clock = clock;
+        self.output = self.counter >= self.sample;
+    }
+}
+impl Default for SolarSensor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drill Dozer and WarioWare Twisted turn the motor on and off with pin 3
+pub struct Rumble {
+    pub active: bool,
+    pub callback: Option<RumbleCallback>,
+}
+impl Rumble {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            callback: None,
+        }
+    }
+
+    pub fn update(&mut self, pins: u8, direction: u8) {
+        if (direction >> 3) & 1 == 0 {
+            return;
+        }
+        let active = (pins >> 3) & 1 == 1;
+        if active == self.active {
+            return;
+        }
+        self.active = active;
+        if let Some(callback) = &mut self.callback {
+            callback(active);
+        }
+    }
+}
+impl Default for Rumble {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// what the gyro reads when it isn't being turned
+const GYRO_CENTRE: i32 = 0x6C0;
+const GYRO_RANGE: f32 = 0x400 as f32;
+
+/// WarioWare Twisted's gyro, pin 0 takes a sample and each falling edge of
+/// pin 1 shifts the next bit of it out on pin 2, top bit first
+pub struct Gyro {
+    // how fast the GBA is being turned, from -1 to 1
+    pub rotation: f32,
+    sample: u16,
+    clock: bool,
+    pub output: bool,
+}
+impl Gyro {
+    pub fn new() -> Self {
+        Self {
+            rotation: 0.,
+            sample: 0,
+            clock: false,
+            output: false,
+        }
+    }
+
+    pub fn update(&mut self, pins: u8) {
+        if pins & 1 == 1 {
+            let offset = (self.rotation.clamp(-1., 1.) * GYRO_RANGE) as i32;
+            self.sample = (GYRO_CENTRE + offset) as u16;
+        }
+
+        let clock = (pins >> 1) & 1 == 1;
+        if self.clock && !clock {
+            self.output = (self.sample >> 15) & 1 == 1;
+            self.sample <<= 1;
+        }
+        self.clock = clock;
+    }
+}
+impl Default for Gyro {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// what the tilt sensor reads when the GBA is flat
+const TILT_CENTRE: i32 = 0x3A0;
+const TILT_RANGE: f32 = 0x200 as f32;
+
+/// the tilt sensor in Yoshi Topsy-Turvy and Koro Koro Puzzle sits in the save area.
+/// 0x55 to 0xE008000 and then 0xAA to 0xE008100 takes a sample, which is read back
+/// from 0xE008200 onwards as 12 bit X and Y values
+pub struct TiltCart {
+    cart: Box<dyn Cartridge>,
+    // both from -1 to 1, positive is right and down
+    pub x: f32,
+    pub y: f32,
+    latch_started: bool,
+    sample: (u16, u16),
+}
+impl TiltCart {
+    pub fn new(cart: Box<dyn Cartridge>) -> Self {
+        Self {
+            cart,
+            x: 0.,
+            y: 0.,
+            latch_started: false,
+            sample: (TILT_CENTRE as u16, TILT_CENTRE as u16),
+        }
+    }
+
+    fn read_tilt(&self, address: u32) -> Option<u8> {
+        let (x, y) = self.sample;
+        match address {
+            0xE008200 => Some(x as u8),
+            // bit 7 says the sample is ready, which is always
+            0xE008300 => Some((x >> 8) as u8 & 0xF | 0x80),
+            0xE008400 => Some(y as u8),
+            0xE008500 => Some((y >> 8) as u8 & 0xF),
+            _ => None,
+        }
+    }
+}
+fn is_tilt_address(address: u32) -> bool {
+    let (upp, low) = split_memory_address(address);
+    upp == 0xE && (0x8000..0x8600).contains(&low)
+}
+impl Cartridge for TiltCart {
+    fn read(&mut self, address: u32) -> u8 {
+        self.peek(address)
+    }
+    fn peek(&self, address: u32) -> u8 {
+        if is_tilt_address(address) {
+            return self.read_tilt(address).unwrap_or(0);
+        }
+        self.cart.peek(address)
+    }
+    fn write(&mut self, address: u32, data: u8, is_8_bit: bool) {
+        if !is_tilt_address(address) {
+            self.cart.write(address, data, is_8_bit);
+            return;
+        }
+        match (address, data) {
+            (0xE008000, 0x55) => self.latch_started = true,
+            (0xE008100, 0xAA) if self.latch_started => {
+                self.latch_started = false;
+                let x = TILT_CENTRE - (self.x.clamp(-1., 1.) * TILT_RANGE) as i32;
+                let y = TILT_CENTRE - (self.y.clamp(-1., 1.) * TILT_RANGE) as i32;
+                self.sample = (x as u16, y as u16);
+            }
+            _ => {}
+        }
+    }
+    fn save_type(&self) -> SaveType { self.cart.save_type() }
+
+    fn dma_started(&mut self, destination: u32, amount: u32) {
+        self.cart.dma_started(destination, amount);
+    }
+    fn save_data(&self) -> &[u8] { self.cart.save_data() }
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.cart.load_save_data(data);
+    }
+    fn save_writes(&self) -> u64 { self.cart.save_writes() }
+    fn rtc_offset(&self) -> Option<i64> { self.cart.rtc_offset() }
+    fn gpio(&mut self) -> Option<&mut Gpio> { self.cart.gpio() }
+    fn tilt(&mut self) -> Option<&mut TiltCart> { Some(self) }
+}
@@ -0,0 +1,303 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: i64 = 86400;
+// the RTC only counts years 00..99, which are 2000..2099
+const YEAR_2000: i64 = 946684800;
+
+/// where the RTC gets the time from, everything is in seconds since 1970 (UTC).
+/// Games can still set the clock themselves, that just gets added on top
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeSource {
+    Host,
+    // never moves, so anything depending on the time is repeatable
+    Fixed(i64),
+    // the host's clock moved forwards (or backwards) by this many seconds
+    Offset(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RtcCommand {
+    Reset,
+    Status,
+    DateTime,
+    Time,
+    // alarms and forced interrupts, nothing needs them so they are just ignored
+    Unused,
+}
+impl RtcCommand {
+    fn length(&self) -> usize {
+        match self {
+            RtcCommand::Reset => 0,
+            RtcCommand::Status => 1,
+            RtcCommand::DateTime => 7,
+            RtcCommand::Time => 3,
+            RtcCommand::Unused => 0,
+        }
+    }
+}
+
+/// the Seiko S-3511 found in the Pokemon games, it's connected to the GPIO port
+/// as SCK (bit 0), SIO (bit 1) and CS (bit 2). Each transfer starts with CS going
+/// high, then a command byte sent MSB first and the data bytes LSB first
+pub struct Rtc {
+    pub time_source: TimeSource,
+    // whatever the game set the clock to, compared to the time source
+    game_offset: i64,
+    // goes up every time the game sets the clock, so the offset gets saved along with the game
+    clock_sets: u64,
+    // bit 6 is 24 hour mode and bit 7 says the power was lost
+    status: u8,
+
+    sck: bool,
+    pub sio: bool,
+    command: Option<(RtcCommand, bool)>,
+    shift: u8,
+    bit: usize,
+    bytes: [u8; 7],
+    byte: usize,
+}
+impl Rtc {
+    pub fn new(time_source: TimeSource) -> Self {
+        Self {
+            time_source,
+            game_offset: 0,
+            clock_sets: 0,
+            status: 0x40,
+
+            sck: false,
+            sio: false,
+            command: None,
+            shift: 0,
+            bit: 0,
+            bytes: [0; 7],
+            byte: 0,
+        }
+    }
+
+    /// the time the game sees, in seconds since 1970
+    pub fn now(&self) -> i64 {
+        let source = match self.time_source {
+            TimeSource::Host => host_time(),
+            TimeSource::Fixed(time) => time,
+            TimeSource::Offset(offset) => host_time() + offset,
+        };
+        return source + self.game_offset;
+    }
+    fn set_time(&mut self, time: i64) {
+        self.game_offset += time - self.now();
+        self.clock_sets += 1;
+    }
+
+    /// the clock only lives on in the cart's battery, so this gets saved next to the .sav
+    pub fn game_offset(&self) -> i64 {
+        self.game_offset
+    }
+    pub fn set_game_offset(&mut self, offset: i64) {
+        self.game_offset = offset;
+    }
+    pub fn clock_sets(&self) -> u64 {
+        self.clock_sets
+    }
+
+    /// called with the pins the GBA is driving whenever it writes to the GPIO port
+    pub fn update(&mut self, pins: u8) {
+        let sck = pins & 1 == 1;
+        let sio = (pins >> 1) & 1;
+        let cs = (pins >> 2) & 1 == 1;
+
+        // CS going low ends whatever transfer was happening
+        if !cs {
+            self.command = None;
+            self.shift = 0;
+            self.bit = 0;
+            self.byte = 0;
+            self.sck = sck;
+            return;
+        }
+        let rising = sck && !self.sck;
+        self.sck = sck;
+        if !rising {
+            return;
+        }
+
+        match self.command {
+            None => {
+                self.shift = (self.shift << 1) | sio;
+                self.bit += 1;
+                if self.bit == 8 {
+                    self.start_command(self.shift);
+                }
+            }
+            Some((command, true)) => {
+                if self.byte >= command.length() {
+                    return;
+                }
+                self.sio = (self.bytes[self.byte] >> self.bit) & 1 == 1;
+                self.next_bit();
+            }
+            Some((command, false)) => {
+                if self.byte >= command.length() {
+                    return;
+                }
+                self.shift |= sio << self.bit;
+                if self.bit == 7 {
+                    self.bytes[self.byte] = self.shift;
+                }
+                self.next_bit();
+                if self.byte == command.length() {
+                    self.finish_write(command);
+                }
+            }
+        }
+    }
+
+    fn next_bit(&mut self) {
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.shift = 0;
+            self.byte += 1;
+        }
+    }
+
+    fn start_command(&mut self, byte: u8) {
+        self.shift = 0;
+        self.bit = 0;
+        self.byte = 0;
+
+        // the top 4 bits are always 0110
+        if byte >> 4 != 0b0110 {
+            return;
+        }
+        let command = match (byte >> 1) & 0x7 {
+            0 => RtcCommand::Reset,
+            1 => RtcCommand::Status,
+            2 => RtcCommand::DateTime,
+            3 => RtcCommand::Time,
+            _ => RtcCommand::Unused,
+        };
+        let is_read = byte & 1 == 1;
+        self.command = Some((command, is_read));
+
+        if command == RtcCommand::Reset {
+            self.status = 0;
+            self.set_time(YEAR_2000);
+        }
+        if is_read {
+            self.bytes = self.encode();
+        }
+    }
+
+    fn finish_write(&mut self, command: RtcCommand) {
+        match command {
+            RtcCommand::Status => self.status = self.bytes[0] & 0x6A,
+            RtcCommand::DateTime => {
+                let date = days_from_civil(
+                    2000 + from_bcd(self.bytes[0]) as i64,
+                    from_bcd(self.bytes[1]) as i64,
+                    from_bcd(self.bytes[2]) as i64,
+                );
+                let time = self.decode_time(&self.bytes[4..7]);
+                self.set_time(date * SECONDS_PER_DAY + time);
+            }
+            RtcCommand::Time => {
+                let today = self.now().div_euclid(SECONDS_PER_DAY);
+                let time = self.decode_time(&self.bytes[0..3]);
+                self.set_time(today * SECONDS_PER_DAY + time);
+            }
+            _ => {}
+        }
+    }
+
+    /// everything a read could ask for, a time read uses the last 3 bytes of this
+    fn encode(&self) -> [u8; 7] {
+        let now = self.now().max(YEAR_2000);
+        let days = now.div_euclid(SECONDS_PER_DAY);
+        let seconds = now.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        // 1970-01-01 was a thursday, 0 is sunday
+        let weekday = (days + 4).rem_euclid(7);
+
+        let hour = seconds / 3600;
+        let pm_flag = match hour >= 12 {
+            true => 0x40,
+            false => 0,
+        };
+        let hour = match self.status & 0x40 != 0 {
+            true => to_bcd(hour as u8),
+            false => to_bcd((hour % 12) as u8),
+        };
+        let time = [
+            hour | pm_flag,
+            to_bcd((seconds / 60 % 60) as u8),
+            to_bcd((seconds % 60) as u8),
+        ];
+
+        match self.command {
+            Some((RtcCommand::Status, _)) => [self.status, 0, 0, 0, 0, 0, 0],
+            Some((RtcCommand::Time, _)) => [time[0], time[1], time[2], 0, 0, 0, 0],
+            _ => [
+                to_bcd(((year - 2000) % 100) as u8),
+                to_bcd(month as u8),
+                to_bcd(day as u8),
+                weekday as u8,
+                time[0],
+                time[1],
+                time[2],
+            ],
+        }
+    }
+
+    fn decode_time(&self, bytes: &[u8]) -> i64 {
+        let mut hour = from_bcd(bytes[0] & 0x3F) as i64;
+        if self.status & 0x40 == 0 && bytes[0] & 0x40 != 0 {
+            hour += 12;
+        }
+        let minute = from_bcd(bytes[1]) as i64;
+        let second = from_bcd(bytes[2]) as i64;
+        return hour * 3600 + minute * 60 + second;
+    }
+}
+
+fn host_time() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(time) => time.as_secs() as i64,
+        Err(_) => 0,
+    }
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xF)
+}
+
+/// days since 1970-01-01 from a date in the gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = match month <= 2 {
+        true => year - 1,
+        false => year,
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+/// the opposite of `days_from_civil`, gives (year, month, day)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_part = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_part + 2) / 5 + 1;
+    let month = match month_part < 10 {
+        true => month_part + 3,
+        false => month_part - 9,
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    return (year, month, day);
+}
@@ -1,8 +1,14 @@
+use crate::apu::{ApuRegisters, DirectSoundFifo};
+use crate::mem::carts::Cartridge;
 use crate::mem::*;
 
 // this has been acquired legally
 pub const BIOS: &[u8; 0x4000] = include_bytes!("bios.bin");
 
+const WAITCNT: u32 = 0x4000204;
+// the extra cycles for the first access to SRAM or any of the cart's wait states
+const CART_WAIT_STATES: [u32; 4] = [4, 3, 2, 8];
+
 pub struct MemLengths;
 impl MemLengths {
     pub const EWRAM: usize = 0x40000;
@@ -12,14 +18,10 @@ impl MemLengths {
     pub const VRAM: usize = 0x18000;
     pub const OAM: usize = 0x400;
     pub const MAX_SRAM: usize = 0x10000;
+    pub const WAVE_RAM: usize = 0x20;
 }
 
-pub fn create_memory(file_name: &str) -> Box<InternalMemory> {
-    let file = match std::fs::read(file_name) {
-        Err(e) => panic!("invalid file provided => {e:?}"),
-        Ok(f) => f,
-    };
-
+pub fn create_memory(cart: Box<dyn Cartridge>) -> Box<InternalMemory> {
     Box::new(InternalMemory {
         ewram: [0; MemLengths::EWRAM],
         iwram: [0; MemLengths::IWRAM],
@@ -27,11 +29,21 @@ pub fn create_memory(file_name: &str) -> Box<InternalMemory> {
         io_reg: [0; MemLengths::IO],
         obj_pall: [0; MemLengths::OBJ],
         oam: [0; MemLengths::OAM],
-        rom: file,
-        sram: [0; MemLengths::MAX_SRAM],
-
-        timer_reload_values: [0; 4],
-        dma_completions: [0; 4],
+        cart,
+        bios: BIOS,
+        wave_ram: [0; MemLengths::WAVE_RAM],
+        fifos: [DirectSoundFifo::new(), DirectSoundFifo::new()],
+
+        now: 0,
+        timers: [Timer::new(); 4],
+        timers_changed: 0,
+        irq_changed: false,
+        dmas: [DmaChannel::new(); 4],
+        dma_active: [false; 4],
+        dma_triggered: 0,
+        dma_latch: 0,
+        last_bios_fetch: 0,
+        executing_bios: true,
     })
 }
 
@@ -42,67 +54,162 @@ pub struct InternalMemory {
     pub io_reg: [u8; MemLengths::IO],
     pub obj_pall: [u8; MemLengths::OBJ],
     pub oam: [u8; MemLengths::OAM],
-    pub rom: Vec<u8>,
-    pub sram: [u8; MemLengths::MAX_SRAM],
-
-    timer_reload_values: [u16; 4],
-    dma_completions: [u32; 4],
+    // the ROM and save memory, 0x8000000 and above all go to this
+    pub cart: Box<dyn Cartridge>,
+    // either the real BIOS or the stand in one for when SWIs are emulated
+    pub bios: &'static [u8; 0x4000],
+    // both banks of the wave channel, only one of them is visible at a time
+    pub wave_ram: [u8; MemLengths::WAVE_RAM],
+    // writes to 0x40000A0..0x40000A8 go here instead of the IO registers
+    pub fifos: [DirectSoundFifo; 2],
+
+    // the scheduler's time, for working out what the timers are up to
+    pub now: u64,
+    timers: [Timer; 4],
+    // a bit for each timer that has been started, stopped or changed and needs rescheduling
+    pub timers_changed: u8,
+    // IE, IF or IME might be different, so whether an interrupt is due needs checking again
+    pub irq_changed: bool,
+    dmas: [DmaChannel; 4],
+    // set once a DMA has started, it stays set until the transfer is done
+    dma_active: [bool; 4],
+    // a bit for each DMA whose start condition just happened, they begin a couple of cycles later
+    pub dma_triggered: u8,
+    // the last thing any DMA transferred, which is what it reads from unmapped memory
+    dma_latch: u32,
+    // what reads of the BIOS get instead once the PC has left it
+    pub last_bios_fetch: u32,
+    pub executing_bios: bool,
 }
 impl InternalMemory {
-    pub fn cpu_read(&self, address: u32) -> Option<u8> {
-        if has_read_lock(address) {
-            return None;
+    /// what the BIOS leaves behind in memory by the time it jumps to the cart
+    pub fn skip_bios(&mut self) {
+        // it finishes with the same code as SoftReset, which clears the top of IWRAM
+        self.iwram[0x7E00..].fill(0);
+        // the intro leaves the screen forced blank
+        self.sys_write_u16(0x4000000, 0x0080);
+        // the rotation scaling parameters are the identity
+        for base in [0x4000020, 0x4000030] {
+            self.sys_write_u16(base, 0x100);
+            self.sys_write_u16(base + 6, 0x100);
         }
+        // SOUNDBIAS at its middle level
+        self.sys_write_u16(0x4000088, 0x0200);
+        // RCNT in general purpose mode
+        self.sys_write_u16(0x4000134, 0x8000);
+        // POSTFLG says this isn't the first boot any more
+        self.sys_write_u8(0x4000300, 0x01);
+    }
 
-        let (upp, low) = split_memory_address(address);
-        if upp == 0x0 && low >= BIOS.len() {
-            return None;
+    /// how many cycles an access of `width` bytes takes, including the one every access takes.
+    /// The cart's wait states come from WAITCNT, and anything on a 16 bit bus takes two goes
+    /// for a word (the second of which is always sequential)
+    pub fn access_cycles(&self, address: u32, width: u32, sequential: bool) -> u32 {
+        let waitcnt = self.sys_read_u16(WAITCNT);
+        let (upp, _) = split_memory_address(address);
+        let (first, rest) = match upp {
+            0x2 => (3, 3),
+            0x5 | 0x6 => (1, 1),
+            0x8..=0xD => {
+                // 0x8 and 0x9 are wait state 0, then 0xA-0xB is 1 and 0xC-0xD is 2
+                let wait_state = (upp - 0x8) / 2;
+                let control = waitcnt >> (2 + wait_state * 3);
+                let sequential_wait = match control >> 2 & 1 == 1 {
+                    true => 1,
+                    false => [2, 4, 8][wait_state as usize],
+                };
+                let non_sequential_cycles = 1 + CART_WAIT_STATES[control as usize & 0b11];
+                let sequential_cycles = 1 + sequential_wait;
+                let first = match sequential {
+                    true => sequential_cycles,
+                    false => non_sequential_cycles,
+                };
+                (first, sequential_cycles)
+            }
+            // SRAM is only ever 8 bit, bigger accesses are still just the one
+            0xE | 0xF => return 1 + CART_WAIT_STATES[waitcnt as usize & 0b11],
+            // BIOS, IWRAM, IO and OAM are all 32 bit
+            _ => return 1,
+        };
+        match width {
+            4 => first + rest,
+            _ => first,
         }
-        if upp > 0xE {
+    }
+
+    /// WAITCNT bit 14 turns on the Game Pak prefetch buffer
+    pub fn prefetch_enabled(&self) -> bool {
+        (self.sys_read_u16(WAITCNT) >> 14) & 1 == 1
+    }
+
+    pub fn cpu_read(&mut self, address: u32) -> Option<u8> {
+        if has_read_lock(address) || self.is_unmapped(address) {
             return None;
         }
     
-        return Some(self.sys_read_u8(address));
+        return Some(self.bus_read_u8(address));
+    }
+    /// nothing is mapped past the BIOS, to 0x1000000-0x1FFFFFF, past the IO registers or above 0x10000000
+    fn is_unmapped(&self, address: u32) -> bool {
+        let (upp, low) = split_memory_address(address);
+        if upp == 0x0 && low >= self.bios.len() {
+            return true;
+        }
+        return upp == 0x1 || (upp == 0x4 && low >= 0x400) || address >= 0x10000000;
     }
     pub fn cpu_write(&mut self, address: u32, data: u8, is_8_bit: bool) {
         if has_write_lock(address) {
             return;
         }
+        let (upp_add, low_add) = split_memory_address(address);
+        if (0x4000200..0x400020C).contains(&address) {
+            self.irq_changed = true;
+        }
         if address == 0x4000202 || address == 0x4000203 {
             self.io_reg[address as usize - 0x4000000] &= !data;
             return;
         }
+        // the FIFO reset bits don't get stored, they just empty the FIFO
+        if address == ApuRegisters::SoundCntH as u32 + 1 {
+            if (data >> 3) & 1 == 1 { self.fifos[0].reset(); }
+            if (data >> 7) & 1 == 1 { self.fifos[1].reset(); }
+            self.sys_write_u8(address, data & !0x88);
+            return;
+        }
 
         // writing to a timer register
-        if address >= 0x4000100 && address <= 0x400010E {
-            let timer_specified = (address - 0x4000100) / 4;
-            // check if its enabling the timer
-            if address % 4 == 2 {
-                let enable_bit = data >> 7 & 1 == 1;
-                if enable_bit {
-                    let timer_reload = self.timer_reload_values[timer_specified as usize];
-                    self.sys_write_u16(address - 2, timer_reload);
-                }
-                self.sys_write_u16(address, data as u16);
-                return;
+        if address >= 0x4000100 && address <= 0x400010F {
+            let timer = (address - 0x4000100) as usize / 4;
+            match address % 4 {
+                0 => self.timers[timer].reload = (self.timers[timer].reload & 0xFF00) | data as u16,
+                1 => self.timers[timer].reload = (self.timers[timer].reload & 0x00FF) | (data as u16) << 8,
+                2 => self.write_timer_control(timer, data),
+                _ => {}
             }
-            if address % 4 >= 2 {
-                return;
+            return;
+        }
+
+        // turning a DMA on copies its registers in, the immediate ones start straight away
+        if (0x40000B0..0x40000E0).contains(&address) && (address - 0x40000B0) % 0xC == 0xB {
+            let channel = (address - 0x40000B0) as usize / 0xC;
+            let was_on = (self.io_reg[low_add] >> 7) & 1 == 1;
+            let is_on = (data >> 7) & 1 == 1;
+            if !was_on && is_on {
+                self.latch_dma(channel);
+                if (data >> 4) & 0x3 == 0 {
+                    self.dma_triggered |= 1 << channel;
+                }
             }
-            
-            // this rounds down anyways which is good
-            let write_upper_byte = address & 1 == 1;
-            let data = data as u16;
-
-            let timer_reset = &mut self.timer_reload_values[timer_specified as usize];
-            match write_upper_byte {
-                true => {*timer_reset &= 0x00FF; *timer_reset |= data << 8},
-                false => {*timer_reset &= 0xFF00; *timer_reset |= data},
+            if !is_on {
+                self.dma_active[channel] = false;
             }
+        }
+
+        if (0x8..=0xF).contains(&upp_add) {
+            self.cart.write(address, data, is_8_bit);
             return;
         }
 
-        let (upp_add, low_add) = split_memory_address(address);
         // why do the video memory buffers not allow 8-bit writes??
         // no clue but it does
         if is_in_video_memory(upp_add) && is_8_bit {
@@ -146,10 +253,20 @@ impl InternalMemory {
         let (upp, low) = split_memory_address(address);
 
         match upp {
-            0x0 => return BIOS[low % BIOS.len()],
+            0x0 => return self.bios[low % self.bios.len()],
             0x2 => return self.ewram[low % MemLengths::EWRAM],
             0x3 => return self.iwram[low % MemLengths::IWRAM],
-            0x4 => return self.io_reg[low % MemLengths::IO],
+            0x4 => {
+                if let Some(index) = self.wave_ram_index(low) {
+                    return self.wave_ram[index];
+                }
+                // the counters are worked out from when they were started
+                if (0x100..0x110).contains(&low) && low % 4 < 2 {
+                    let counter = self.timer_counter((low - 0x100) / 4);
+                    return (counter >> ((low % 2) * 8)) as u8;
+                }
+                return self.io_reg[low % MemLengths::IO];
+            }
             0x5 => return self.obj_pall[low % MemLengths::OBJ],
             0x6 => {
                 let base = low & 0x1FFFF;
@@ -159,13 +276,7 @@ impl InternalMemory {
                 return self.vram[base];
             }
             0x7 => return self.oam[low % MemLengths::OAM],
-            0x8..=0xD => {
-                if upp % 2 == 1 {
-                    return self.rom[(low + 0x1000000) % self.rom.len()];
-                }
-                return self.rom[low % self.rom.len()];
-            }
-            0xE => return self.sram[low % MemLengths::MAX_SRAM],
+            0x8..=0xF => return self.cart.peek(address),
             _ => panic!("this should never be read from"),
         }
     }
@@ -200,7 +311,17 @@ impl InternalMemory {
             0x0 => panic!("cannot make a write to the BIOS"),
             0x2 => self.ewram[low % MemLengths::EWRAM] = data,
             0x3 => self.iwram[low % MemLengths::IWRAM] = data,
-            0x4 => self.io_reg[low % MemLengths::IO] = data,
+            0x4 => {
+                if let Some(index) = self.wave_ram_index(low) {
+                    self.wave_ram[index] = data;
+                    return;
+                }
+                if (0xA0..0xA8).contains(&low) {
+                    self.fifos[(low - 0xA0) / 4].push(data);
+                    return;
+                }
+                self.io_reg[low % MemLengths::IO] = data;
+            }
             0x5 => self.obj_pall[low % MemLengths::OBJ] = data,
             0x6 => {
                 // 64k-32k (then the 32k is mirrored again) (then everything is mirrored again)
@@ -212,11 +333,116 @@ impl InternalMemory {
                 self.vram[base] = data;
             }
             0x7 => self.oam[low % MemLengths::OAM] = data,
-            0xE => self.sram[low % MemLengths::MAX_SRAM] = data,
-            _ => println!("cannot write to ROM {address:X}, {data:X}"),
+            // only DMA gets here, which is never 8-bit
+            0x8..=0xF => self.cart.write(address, data, false),
+            _ => println!("cannot write to {address:X}, {data:X}"),
         };
     }
 
+    /// reads the way the CPU and DMA do, the cart is allowed to react
+    /// to these where `sys_read_u8` only peeks at it. The BIOS is protected
+    /// from anything that isn't running from inside of it
+    fn bus_read_u8(&mut self, address: u32) -> u8 {
+        let (upp, low) = split_memory_address(address);
+        match upp {
+            0x0 if !self.executing_bios => (self.last_bios_fetch >> ((low & 0b11) * 8)) as u8,
+            0x8..=0xF => self.cart.read(address),
+            _ => self.sys_read_u8(address),
+        }
+    }
+    fn bus_read_u16(&mut self, address: u32) -> u16 {
+        let base = address & !(0b1);
+
+        lil_end_combine_u16(
+            self.bus_read_u8(base + 0),
+            self.bus_read_u8(base + 1),
+        )
+    }
+    fn bus_read_u32(&mut self, address: u32) -> u32 {
+        let base = address & !(0b11);
+
+        lil_end_combine_u32(
+            self.bus_read_u8(base + 0),
+            self.bus_read_u8(base + 1),
+            self.bus_read_u8(base + 2),
+            self.bus_read_u8(base + 3),
+        )
+    }
+
+    /// sets bits in IF, whether they actually cause an interrupt is up to IE and IME
+    pub fn request_interrupt(&mut self, bits: u16) {
+        let i_flag = self.sys_read_u16(0x4000202);
+        self.sys_write_u16(0x4000202, i_flag | bits);
+        self.irq_changed = true;
+    }
+    /// whether there is an interrupt for the CPU to take if it's allowed to
+    pub fn interrupt_pending(&self) -> bool {
+        let ie = self.sys_read_u16(0x4000200);
+        let i_flag = self.sys_read_u16(0x4000202);
+        return ie & i_flag != 0;
+    }
+
+    /// the DMAs which start on `dma_start` (1 for V-blank and 2 for H-blank) get going
+    pub fn trigger_dma(&mut self, dma_start: u16) {
+        for i in 0..4 {
+            let cnt = self.sys_read_u16(DMARegisters::Control as u32 + (i as u32 * 0xC));
+            if (cnt >> 15) & 1 == 1 && (cnt >> 12) & 0x3 == dma_start {
+                self.dma_triggered |= 1 << i;
+            }
+        }
+    }
+    /// the start up delay after a trigger is over, unless it was turned off in the meantime
+    pub fn start_dma(&mut self, channel: usize) {
+        let cnt = self.sys_read_u16(DMARegisters::Control as u32 + (channel as u32 * 0xC));
+        if (cnt >> 15) & 1 == 1 {
+            self.dma_active[channel] = true;
+        }
+    }
+    pub fn dma_running(&self) -> bool {
+        self.dma_active.contains(&true)
+    }
+
+    fn timer_counter(&self, timer: usize) -> u16 {
+        let control = self.io_reg[0x102 + timer * 4] as u16;
+        let t = &self.timers[timer];
+        if !timer_counts_cycles(timer, control) {
+            return t.counter;
+        }
+        let prescale = FREQUENCY[control as usize & 0b11];
+        let ticks = (self.now - t.start) / prescale;
+        return t.counter.wrapping_add(ticks as u16);
+    }
+    fn write_timer_control(&mut self, timer: usize, data: u8) {
+        let old_control = self.io_reg[0x102 + timer * 4];
+        // it stops counting wherever it is up to, then carries on from there with the new settings
+        self.timers[timer].counter = self.timer_counter(timer);
+        self.timers[timer].start = self.now;
+        if (old_control >> 7) & 1 == 0 && (data >> 7) & 1 == 1 {
+            self.timers[timer].counter = self.timers[timer].reload;
+        }
+        self.io_reg[0x102 + timer * 4] = data;
+        self.timers_changed |= 1 << timer;
+    }
+    /// when the timer will next overflow, if it is counting cycles at all
+    pub fn timer_overflow_time(&self, timer: usize) -> Option<u64> {
+        let control = self.io_reg[0x102 + timer * 4] as u16;
+        if !timer_counts_cycles(timer, control) {
+            return None;
+        }
+        let t = &self.timers[timer];
+        let prescale = FREQUENCY[control as usize & 0b11];
+        return Some(t.start + (0x10000 - t.counter as u64) * prescale);
+    }
+
+    /// the CPU can only see the wave RAM bank which isn't currently being played
+    fn wave_ram_index(&self, low: usize) -> Option<usize> {
+        if !(0x90..0xA0).contains(&low) {
+            return None;
+        }
+        let playing_bank = (self.io_reg[0x70] >> 6) as usize & 1;
+        let visible_bank = playing_bank ^ 1;
+        return Some(visible_bank * 0x10 + (low - 0x90));
+    }
 }
 // since DMA takes several cycles, its best to just have it be its own thing
 pub enum DMARegisters {
@@ -225,180 +451,275 @@ pub enum DMARegisters {
     Amount = 0x40000B8,
     Control = 0x40000BA,
 }
-pub fn dma_tick(mem: &mut Box<InternalMemory>) -> bool {
+// DMA0 can only use internal memory, and only DMA3 can write to the cart
+const DMA_SOURCE_MASKS: [u32; 4] = [0x07FFFFFF, 0x0FFFFFFF, 0x0FFFFFFF, 0x0FFFFFFF];
+const DMA_DESTINATION_MASKS: [u32; 4] = [0x07FFFFFF, 0x07FFFFFF, 0x07FFFFFF, 0x0FFFFFFF];
+
+/// SAD, DAD and CNT_L are copied in here when a channel gets turned on, so
+/// writing to them afterwards does nothing until it's turned on again
+#[derive(Clone, Copy)]
+struct DmaChannel {
+    source: u32,
+    destination: u32,
+    // units left in this transfer
+    remaining: u32,
+    // the first unit of a transfer is non-sequential
+    started: bool,
+}
+impl DmaChannel {
+    const fn new() -> Self {
+        Self { source: 0, destination: 0, remaining: 0, started: false }
+    }
+}
+
+impl InternalMemory {
+    // 0 is the most a channel can do, DMA3 has a 16 bit count and the rest have 14
+    fn dma_count(&self, channel: usize) -> u32 {
+        let cnt_l = self.sys_read_u16(DMARegisters::Amount as u32 + channel as u32 * 0xC) as u32;
+        let (count, max) = match channel {
+            3 => (cnt_l, 0x10000),
+            _ => (cnt_l & 0x3FFF, 0x4000),
+        };
+        match count {
+            0 => max,
+            _ => count,
+        }
+    }
+    fn latch_dma(&mut self, channel: usize) {
+        let offset = channel as u32 * 0xC;
+        let source = self.sys_read_u32(DMARegisters::SAD as u32 + offset);
+        let destination = self.sys_read_u32(DMARegisters::DAD as u32 + offset);
+        self.dmas[channel] = DmaChannel {
+            source: source & DMA_SOURCE_MASKS[channel],
+            destination: destination & DMA_DESTINATION_MASKS[channel],
+            remaining: self.dma_count(channel),
+            started: false,
+        };
+        self.dma_active[channel] = false;
+    }
+
+    fn dma_read(&mut self, address: u32, width: u32) -> u32 {
+        if !self.is_unmapped(address) {
+            self.dma_latch = match width {
+                4 => self.bus_read_u32(address),
+                _ => self.bus_read_u16(address) as u32 * 0x10001,
+            };
+        }
+        return match width {
+            4 => self.dma_latch,
+            _ => (self.dma_latch >> ((address & 0b10) * 8)) & 0xFFFF,
+        };
+    }
+    fn dma_write(&mut self, address: u32, data: u32, width: u32) {
+        let (upp, _) = split_memory_address(address);
+        if upp <= 0x1 {
+            return;
+        }
+        // IO goes through the same side effects as the CPU writing there
+        if upp == 0x4 {
+            let base = address & !(width - 1);
+            for i in 0..width {
+                self.cpu_write(base + i, (data >> (i * 8)) as u8, false);
+            }
+            return;
+        }
+        self.sys_write_u16(address, data as u16);
+        if width == 4 {
+            self.sys_write_u16(address + 2, (data >> 16) as u16);
+        }
+    }
+}
+
+// how far each address control moves the address by each unit, 3 is prohibited for the
+// source but acts like increment, and for the destination it's increment and reload
+fn dma_step(control: u16) -> i32 {
+    match control {
+        0 | 3 => 1,
+        1 => -1,
+        _ => 0,
+    }
+}
+
+/// transfers one unit for the highest priority DMA which has started, returning
+/// the cycles it took. `None` means nothing is running and the CPU can have the bus
+pub fn dma_tick(mem: &mut Box<InternalMemory>) -> Option<u32> {
     let mut dma_transfer = None;
     for i in 0..=3 {
-        let cnt = mem.sys_read_u16(DMARegisters::Control as u32 + (i*0xC));
+        let cnt = mem.sys_read_u16(DMARegisters::Control as u32 + (i as u32 * 0xC));
         let is_on = (cnt >> 15) & 1 == 1;
 
         // highest priority goes 0 -> 3
-        if is_on {
+        if is_on && mem.dma_active[i] {
             dma_transfer = Some((i, cnt));
             break;
         }
     }
 
     // no dma transfer active rn
-    if let None = dma_transfer {
-        return false;
-    }
-    let (i, cnt) = dma_transfer.unwrap();
+    let (i, cnt) = dma_transfer?;
 
-    let base_src_address = mem.sys_read_u32(DMARegisters::SAD as u32 + (i*0xC)) & 0x0FFFFFFF; // top bits ignored
-    let base_dst_address = mem.sys_read_u32(DMARegisters::DAD as u32 + (i*0xC)) & 0x0FFFFFFF; // top bits ignored
-    let cnt_l = mem.sys_read_u16(DMARegisters::Amount as u32 + (i*0xC)) as u32;
-    let amount = match i {
-        3 => match cnt_l {
-            0 => 0x10000,
-            _ => cnt_l,
-        }
-        _ => match cnt_l {
-            0 => 0x4000,
-            _ => cnt_l,
-        }
-    };
-
-    let dst_ctrl = (cnt >> 5) & 0x3;
-    let src_ctrl = (cnt >> 7) & 0x3;
+    let mut dst_ctrl = (cnt >> 5) & 0x3;
+    let mut src_ctrl = (cnt >> 7) & 0x3;
 
     let repeat = (cnt >> 9) & 1 == 1;
-    let quantities = (cnt >> 10) & 1 == 1;
+    let mut quantities = (cnt >> 10) & 1 == 1;
     let _drq = (cnt >> 11) & 1 == 1; // this isn't possible to implement????
 
     let dma_start = (cnt >> 12) & 0x3;
     let irq_call = (cnt >> 14) & 1 == 1;
 
-    let dispstat = mem.sys_read_u16(0x4000004);
-    match dma_start {
-        0 => {}
-        1 => if (dispstat >> 0) & 1 == 0 { return false; }
-        2 => if (dispstat >> 1) & 1 == 0 { return false; }
-        3 => {
-            // special so must be false
-            assert!(base_dst_address == 0x40000A0 || base_dst_address == 0x40000A4);
-            // turn that shit off :P
-            mem.sys_write_u16(DMARegisters::Control as u32 + i*0xC, cnt & 0x7FFF);
-            return false;
-        }
-        _ => unreachable!(),
+    let sound_fifo = dma_start == 3 && (i == 1 || i == 2);
+    if dma_start == 3 && !sound_fifo {
+        // turn that shit off :P
+        mem.sys_write_u16(DMARegisters::Control as u32 + i as u32 * 0xC, cnt & 0x7FFF);
+        mem.dma_active[i] = false;
+        return None;
     }
 
-    if i == 3 && split_memory_address(base_src_address).0 == 0xD {
-        println!("{amount}");
+    // sound DMA always sends 4 words to the same address, no matter the settings
+    if sound_fifo {
+        dst_ctrl = 2;
+        quantities = true;
     }
 
-    let done_already = mem.dma_completions[i as usize];
-    let src_address = match src_ctrl {
-        0 => base_src_address + done_already,
-        1 => base_src_address - done_already,
-        2 => base_src_address,
-        _ => unreachable!("invalid DMA transfer"),
-    };
+    let mut dma = mem.dmas[i];
+    if !dma.started {
+        if sound_fifo {
+            dma.remaining = 4;
+        }
+        if i == 3 {
+            mem.cart.dma_started(dma.destination, dma.remaining);
+        }
+    }
+    // the cart can only be read forwards
+    let (src_upp, _) = split_memory_address(dma.source);
+    let (dst_upp, _) = split_memory_address(dma.destination);
+    if (0x8..=0xD).contains(&src_upp) {
+        src_ctrl = 0;
+    }
 
-    let dst_address = match dst_ctrl {
-        0 => base_dst_address + done_already,
-        1 => base_dst_address - done_already,
-        2 => base_dst_address,
-        3 => base_dst_address + done_already,
-        _ => unreachable!(),
+    let width = match quantities {
+        true => 4,
+        false => 2,
     };
+    let src_address = dma.source & !(width - 1);
+    let dst_address = dma.destination & !(width - 1);
 
-    match quantities {
-        true => {
-            // 32-bit
-            let read = mem.sys_read_u32(src_address);
-            mem.sys_write_u16(dst_address + 0, read as u16);
-            mem.sys_write_u16(dst_address + 2, (read >> 16) as u16);
+    // the first unit is non-sequential, then it carries on from there
+    let sequential = dma.started;
+    let mut cycles = mem.access_cycles(src_address, width, sequential) + mem.access_cycles(dst_address, width, sequential);
 
-            mem.dma_completions[i as usize] += 4;
-        }
-        false => {
-            // 16-bit
-            let read = mem.sys_read_u16(src_address);
-            mem.sys_write_u16(dst_address, read);
+    let data = mem.dma_read(src_address, width);
+    mem.dma_write(dst_address, data, width);
 
-            mem.dma_completions[i as usize] += 2;
-        }
-    }
-    // DMA is finished
-    let final_amount = match quantities {
-        true => amount * 4,
-        false => amount * 2,
-    };
+    dma.source = dma.source.wrapping_add((dma_step(src_ctrl) * width as i32) as u32);
+    dma.destination = dma.destination.wrapping_add((dma_step(dst_ctrl) * width as i32) as u32);
+    dma.remaining -= 1;
+    dma.started = true;
 
-    if mem.dma_completions[i as usize] >= final_amount {
+    // DMA is finished
+    if dma.remaining == 0 {
+        // it takes 2 I cycles to finish, or 4 if it was between two parts of the cart
+        cycles += match (0x8..=0xF).contains(&src_upp) && (0x8..=0xF).contains(&dst_upp) {
+            true => 4,
+            false => 2,
+        };
         if irq_call {
-            let mut i_flag = mem.sys_read_u16(0x4000202);
-            i_flag |= 1 << (8 + i);
-
-            mem.sys_write_u16(0x4000202, i_flag);
+            mem.request_interrupt(1 << (8 + i));
         }
 
-        mem.dma_completions[i as usize] = 0;
-        if repeat {
-            return true;
+        // repeating ones wait for their start condition to happen again, with the count
+        // reloaded. The source always carries on from where it got to
+        dma.started = false;
+        mem.dma_active[i] = false;
+        match repeat && dma_start != 0 {
+            true => {
+                dma.remaining = mem.dma_count(i);
+                if dst_ctrl == 3 {
+                    let destination = mem.sys_read_u32(DMARegisters::DAD as u32 + i as u32 * 0xC);
+                    dma.destination = destination & DMA_DESTINATION_MASKS[i];
+                }
+            }
+            // clear the top bit
+            false => mem.sys_write_u16(DMARegisters::Control as u32 + i as u32 * 0xC, cnt & 0x7FFF),
         }
-
-        // clear the top bit
-        mem.sys_write_u16(DMARegisters::Control as u32 + i*0xC, cnt & 0x7FFF);
-        return false;
     }
 
-    return true;
+    mem.dmas[i] = dma;
+    return Some(cycles);
 }
 
-
-const BASE_TIMER_ADDRESS: u32 = 0x4000100;
-const FREQUENCY: [u32; 4] = [1, 64, 256, 1024];
-pub fn update_timer(memory: &mut Box<InternalMemory>, old_cycles: &mut u32, new_cycles: u32) {
-    let total_cycles = *old_cycles + new_cycles;
-    let mut prev_cascade = false;
-
-    for timer in 0..=3 {
-        // the address
-        let timer_address = BASE_TIMER_ADDRESS + (timer * 4);
-        let control = memory.sys_read_u16(timer_address + 2);
-
-        // its not turned on
-        let timer_enable = (control >> 7) & 1 == 1;
-        if !timer_enable {
-            prev_cascade = false;
+/// called when a FIFO is half empty, whichever of DMA1/DMA2 is in sound mode and
+/// pointed at that FIFO is the one that refills it
+pub fn request_sound_dma(mem: &mut InternalMemory, fifo_address: u32) {
+    for i in 1..=2 {
+        let cnt = mem.sys_read_u16(DMARegisters::Control as u32 + (i as u32 * 0xC));
+        let is_on = (cnt >> 15) & 1 == 1;
+        let dma_start = (cnt >> 12) & 0x3;
+        if !is_on || dma_start != 3 {
             continue;
         }
 
-        // get the frequency
-        let frequency_bits = control & 0b11;
-        let frequency = FREQUENCY[frequency_bits as usize];
+        if mem.dmas[i].destination == fifo_address {
+            mem.dma_triggered |= 1 << i;
+        }
+    }
+}
 
-        // cycles already done
-        let timer_cycles = memory.sys_read_u16(timer_address);
+const BASE_TIMER_ADDRESS: u32 = 0x4000100;
+const FREQUENCY: [u64; 4] = [1, 64, 256, 1024];
+
+#[derive(Clone, Copy)]
+struct Timer {
+    reload: u16,
+    // what it was at `start`, count-up timers only change when the one before overflows
+    counter: u16,
+    start: u64,
+}
+impl Timer {
+    const fn new() -> Self {
+        Self { reload: 0, counter: 0, start: 0 }
+    }
+}
 
-        let count_up_timer = (control >> 2) & 1 == 1;
-        let (new_timer_cycles, overflow) = match count_up_timer {
-            true => timer_cycles.overflowing_add(prev_cascade as u16),
-            false => {
-                // everytime the total cycles passes a multiple of the frequency, then add 1 no?
-                let cycles_to_add = total_cycles/frequency - *old_cycles/frequency;
-                timer_cycles.overflowing_add(cycles_to_add as u16)
-            }
-        };
-        prev_cascade = overflow;
+// count-up doesn't do anything for timer 0
+fn timer_counts_cycles(timer: usize, control: u16) -> bool {
+    let timer_enable = (control >> 7) & 1 == 1;
+    let count_up_timer = timer != 0 && (control >> 2) & 1 == 1;
+    return timer_enable && !count_up_timer;
+}
 
-        let interrupt_flag = (control >> 6) & 1 == 1;
-        
-        // we need to call the interrupt
-        if overflow && interrupt_flag {
-            let mut interrupt_flag = memory.sys_read_u16(0x4000202);
-            interrupt_flag |= 1 << (timer + 3);
+/// the timer overflowed at `time` so it reloads, along with anything counting up from it.
+/// The timers which overflowed are returned, the sound FIFOs need to know about 0 and 1
+pub fn timer_overflow(memory: &mut InternalMemory, timer: usize, time: u64) -> [bool; 4] {
+    let mut overflows = [false; 4];
+    let mut timer = timer;
+    loop {
+        let control = memory.sys_read_u16(BASE_TIMER_ADDRESS + timer as u32 * 4 + 2);
+        memory.timers[timer].counter = memory.timers[timer].reload;
+        memory.timers[timer].start = time;
+        overflows[timer] = true;
 
-            memory.sys_write_u16(0x4000202, interrupt_flag);
+        let interrupt_flag = (control >> 6) & 1 == 1;
+        if interrupt_flag {
+            memory.request_interrupt(1 << (timer + 3));
         }
 
-        match overflow {
-            true => memory.sys_write_u16(timer_address, memory.timer_reload_values[timer as usize]),
-            false => memory.sys_write_u16(timer_address, new_timer_cycles as u16),
+        // the next one only cares if it is counting up
+        if timer == 3 {
+            break;
         }
+        let next_control = memory.sys_read_u16(BASE_TIMER_ADDRESS + timer as u32 * 4 + 6);
+        let cascades = (next_control >> 7) & 1 == 1 && (next_control >> 2) & 1 == 1;
+        if !cascades {
+            break;
+        }
+        let (counter, overflow) = memory.timers[timer + 1].counter.overflowing_add(1);
+        memory.timers[timer + 1].counter = counter;
+        if !overflow {
+            break;
+        }
+        timer += 1;
     }
-
-    *old_cycles = total_cycles % 1024;
+    return overflows;
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 pub mod memory;
 pub mod bus;
 pub mod carts;
+pub mod prefetch;
 
 /// output =>
 /// 0bBBBBBBBBAAAAAAAA
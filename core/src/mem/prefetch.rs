@@ -0,0 +1,82 @@
+// it holds up to 8 halfwords, whatever the CPU is running
+const PREFETCH_HALFWORDS: u32 = 8;
+
+/// the Game Pak prefetch unit. While the CPU isn't using the cart bus (it's busy with
+/// I cycles or accessing other memory) it carries on reading the ROM after the last
+/// opcode fetched from it, so sequential code can come out of here in a single cycle
+pub struct Prefetch {
+    active: bool,
+    // the address of the first halfword in the buffer
+    head: u32,
+    // how many halfwords are ready
+    count: u32,
+    // the cycles until the halfword being read now is ready
+    countdown: u32,
+}
+impl Prefetch {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            head: 0,
+            count: 0,
+            countdown: 0,
+        }
+    }
+
+    /// anything else using the cart empties the buffer
+    pub fn stop(&mut self) {
+        self.active = false;
+        self.count = 0;
+    }
+    /// starts over just after an opcode fetch the buffer didn't have
+    pub fn restart(&mut self, address: u32, sequential_cycles: u32) {
+        self.active = true;
+        self.head = address;
+        self.count = 0;
+        self.countdown = sequential_cycles;
+    }
+
+    /// whether the opcode at `address` is either in the buffer or on its way
+    pub fn has(&self, address: u32) -> bool {
+        self.active && self.head == address
+    }
+
+    /// the cart bus has been free for `cycles`, `sequential_cycles` is how long each halfword takes
+    pub fn step(&mut self, mut cycles: u32, sequential_cycles: u32) {
+        while self.active && self.count < PREFETCH_HALFWORDS && cycles > 0 {
+            if cycles < self.countdown {
+                self.countdown -= cycles;
+                return;
+            }
+            cycles -= self.countdown;
+            self.count += 1;
+            self.countdown = sequential_cycles;
+        }
+    }
+
+    /// takes an opcode of `width` bytes from the front of the buffer, returning the cycles that took.
+    /// It's a single cycle if it was already there, otherwise it's however long is left to wait for it
+    pub fn take(&mut self, width: u32, sequential_cycles: u32) -> u32 {
+        let mut waited = 0;
+        for _ in 0..width / 2 {
+            if self.count == 0 {
+                waited += self.countdown;
+                self.count += 1;
+                self.countdown = sequential_cycles;
+            }
+            self.count -= 1;
+            self.head = self.head.wrapping_add(2);
+        }
+
+        if waited == 0 {
+            self.step(1, sequential_cycles);
+            return 1;
+        }
+        return waited;
+    }
+}
+impl Default for Prefetch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
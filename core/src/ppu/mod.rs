@@ -63,7 +63,11 @@ const LCD_HEIGHT: usize = 160;
 const LCD_WIDTH: usize = 240;
 const VRAM_BASE: u32 = 0x6000000;
 const PALETTE_BASE: u32 = 0x5000000;
-const DOTS_PER_FRAME: usize = (LCD_WIDTH + 68) * (LCD_HEIGHT + 68);
+const LINES_PER_FRAME: usize = LCD_HEIGHT + 68;
+const CYCLES_PER_DOT: u64 = 4;
+/// how long the visible part of a line and the H-blank after it last
+pub const HDRAW_CYCLES: u64 = LCD_WIDTH as u64 * CYCLES_PER_DOT;
+pub const HBLANK_CYCLES: u64 = 68 * CYCLES_PER_DOT;
 
 enum PpuRegisters {
     DispCnt = 0x4000000,
@@ -85,85 +89,76 @@ enum PpuRegisters {
 }
 pub struct Ppu {
     pub new_screen: bool,
-    elapsed_time: usize, // represents the number of dots elapsed
     pub stored_screen: Vec<u16>,
 }
 impl Ppu {
     pub fn new() -> Self {
         Self { 
             new_screen: false,
-            elapsed_time: 0,
             stored_screen: Vec::new(),
         }
     }
     pub fn acknowledge_frame(&mut self) {
         self.new_screen = false;
-        self.elapsed_time = 0;
         self.stored_screen.clear();
     }
 }
 
-pub fn tick_ppu<P: PpuInterface>(ppu: &mut Ppu, memory: &mut P) {
+/// the visible part of the line is over, so it gets drawn now
+pub fn start_hblank<P: PpuInterface>(ppu: &mut Ppu, memory: &mut P) {
     let dispcnt = memory.read_vram_u16(PpuRegisters::DispCnt as u32);
-
-    // the line count we had last time, doesnt match the one this time
-    let dispstat = memory.read_vram_u16(PpuRegisters::DispStat as u32);
-    let mut vcount = memory.read_vram_u16(PpuRegisters::VCount as u32);
-
-    let new_line = ppu.elapsed_time / (LCD_WIDTH + 68) != vcount as usize;
-    if new_line {
-        if vcount < LCD_HEIGHT as u16 {
-            let mut layers = LineLayers::blank();
-
-            // clear it for the new line
-            let bg_mode = dispcnt & 0b111;
-            match bg_mode {
-                0 => bg_mode_0(&mut layers, memory, vcount as u32),
-                1 => bg_mode_1(&mut layers, memory, vcount as u32),
-                2 => bg_mode_2(&mut layers, memory, vcount as u32),
-                3 => bg_mode_3(&mut layers, memory, vcount),
-                4 => bg_mode_4(&mut layers, memory, vcount),
-                5 => bg_mode_5(&mut layers, memory, vcount),
-                _ => panic!("you can't set the bg_mode to {bg_mode}"),
-            };
-            oam_scan(&mut layers, memory, vcount, dispcnt);
-
-            let combo = accumulate_and_palette(&layers, memory);
-            ppu.stored_screen.extend(combo);
-        }
-        vcount += 1;
-        if vcount as usize >= (LCD_HEIGHT + 68) {
-            vcount = 0;
-            ppu.new_screen = true;
-        }
-        memory.write_vram_u16(PpuRegisters::VCount as u32, vcount as u16);
+    let mut dispstat = memory.read_vram_u16(PpuRegisters::DispStat as u32);
+    let vcount = memory.read_vram_u16(PpuRegisters::VCount as u32);
+
+    if vcount < LCD_HEIGHT as u16 {
+        let mut layers = LineLayers::blank();
+
+        // clear it for the new line
+        let bg_mode = dispcnt & 0b111;
+        match bg_mode {
+            0 => bg_mode_0(&mut layers, memory, vcount as u32),
+            1 => bg_mode_1(&mut layers, memory, vcount as u32),
+            2 => bg_mode_2(&mut layers, memory, vcount as u32),
+            3 => bg_mode_3(&mut layers, memory, vcount),
+            4 => bg_mode_4(&mut layers, memory, vcount),
+            5 => bg_mode_5(&mut layers, memory, vcount),
+            _ => panic!("you can't set the bg_mode to {bg_mode}"),
+        };
+        oam_scan(&mut layers, memory, vcount, dispcnt);
+
+        let combo = accumulate_and_palette(&layers, memory);
+        ppu.stored_screen.extend(combo);
     }
 
-    update_registers(ppu, memory, dispstat, vcount);
+    dispstat |= 1<<1;
+    if (dispstat >> 4) & 1 == 1 {
+        memory.request_interrupt(1<<1);
+    }
+    memory.write_vram_u16(PpuRegisters::DispStat as u32, dispstat);
 }
 
-fn update_registers<P: PpuInterface>(ppu: &mut Ppu, memory: &mut P, mut dispstat: u16, vcount: u16) {
-    // work in progress
-    ppu.elapsed_time += 1;
-    
-    // new frame
-    if ppu.elapsed_time >= DOTS_PER_FRAME {
-        ppu.elapsed_time = 0;
-        dispstat &= !(0b11<<0);
-    }
+/// moves VCOUNT on to the next line, the V-blank starts once all of them have been drawn
+pub fn end_line<P: PpuInterface>(ppu: &mut Ppu, memory: &mut P) {
+    let mut dispstat = memory.read_vram_u16(PpuRegisters::DispStat as u32);
+    let mut vcount = memory.read_vram_u16(PpuRegisters::VCount as u32);
 
-    // V-blank flag
-    let in_vblank = ppu.elapsed_time / (LCD_WIDTH + 68) >= LCD_HEIGHT;
-    match in_vblank {
-        true => dispstat |= 1<<0,
-        false => dispstat &= !(1<<0),
+    vcount += 1;
+    if vcount as usize >= LINES_PER_FRAME {
+        vcount = 0;
     }
-
-    // H-blank flag
-    let in_hblank = ppu.elapsed_time % (LCD_WIDTH + 68) >= LCD_WIDTH;
-    match in_hblank {
-        true => dispstat |= 1<<1,
-        false => dispstat &= !(1<<1),
+    memory.write_vram_u16(PpuRegisters::VCount as u32, vcount);
+    dispstat &= !(1<<1);
+
+    // V-blank flag, it's already off for the last line
+    if vcount == LCD_HEIGHT as u16 {
+        dispstat |= 1<<0;
+        ppu.new_screen = true;
+        if (dispstat >> 3) & 1 == 1 {
+            memory.request_interrupt(1<<0);
+        }
+    }
+    if vcount as usize == LINES_PER_FRAME - 1 {
+        dispstat &= !(1<<0);
     }
 
     let vcount_lyc = (dispstat >> 8) & 0xFF;
@@ -171,12 +166,9 @@ fn update_registers<P: PpuInterface>(ppu: &mut Ppu, memory: &mut P, mut dispstat
     match vcounter_match {
         true => dispstat |= 1<<2,
         false => dispstat &= !(1<<2),
-    }  
-
-    let mut ie = memory.read_vram_u16(0x4000202);
-    ie &= !0b111;
-    ie |= dispstat & 0b111;
-
-    memory.write_vram_u16(0x4000202, ie);
+    }
+    if vcounter_match && (dispstat >> 5) & 1 == 1 {
+        memory.request_interrupt(1<<2);
+    }
     memory.write_vram_u16(PpuRegisters::DispStat as u32, dispstat);
 }
\ No newline at end of file
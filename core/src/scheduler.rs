@@ -0,0 +1,70 @@
+/// everything that happens at a set time rather than because of an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    TimerOverflow(usize),
+    // 240 dots into a line, when it gets drawn
+    HBlank,
+    // the end of the H-blank, VCOUNT moves on to the next line
+    LineEnd,
+    ApuSample,
+    // a DMA's start up delay is over
+    DmaStart(usize),
+    // something might have changed IE, IF or IME
+    Irq,
+}
+
+/// a timestamped queue of `Event`s, time is in CPU cycles since the emulator started
+pub struct Scheduler {
+    now: u64,
+    // sorted so the soonest is at the end
+    events: Vec<(u64, Event)>,
+}
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            now: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+    pub fn advance(&mut self, cycles: u32) {
+        self.now += cycles as u64;
+    }
+
+    pub fn schedule(&mut self, event: Event, cycles_from_now: u64) {
+        self.schedule_at(event, self.now + cycles_from_now);
+    }
+    /// events at the same time happen in the order they were scheduled
+    pub fn schedule_at(&mut self, event: Event, time: u64) {
+        let index = self.events.partition_point(|(other, _)| *other > time);
+        self.events.insert(index, (time, event));
+    }
+    pub fn cancel(&mut self, event: Event) {
+        self.events.retain(|(_, other)| *other != event);
+    }
+
+    /// when the CPU next has to stop, `u64::MAX` if nothing is waiting
+    pub fn next_event_time(&self) -> u64 {
+        match self.events.last() {
+            Some((time, _)) => *time,
+            None => u64::MAX,
+        }
+    }
+    /// the soonest event if it's due, along with the time it was meant for
+    pub fn pop_due(&mut self) -> Option<(Event, u64)> {
+        let (time, event) = *self.events.last()?;
+        if time > self.now {
+            return None;
+        }
+        self.events.pop();
+        return Some((event, time));
+    }
+}
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
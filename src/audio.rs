@@ -0,0 +1,234 @@
+use gba_core::apu::DEFAULT_SAMPLE_RATE;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+/// wherever the emulator's samples end up, these are always interleaved (left, right)
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+    fn push(&mut self, samples: &[i16]);
+    /// how full the sink's queue is between 0 and 1, sinks which
+    /// don't play in real time don't have one
+    fn fill_level(&self) -> Option<f32>;
+}
+
+/// the device (if there is one) has to stay alive for sound to play, but it can't be
+/// sent between threads so it stays with the window while the sink goes to the emulator
+pub struct AudioOutput {
+    #[cfg(feature = "audio")]
+    _stream: Option<cpal::Stream>,
+}
+
+/// a wav file is used if one is asked for, otherwise the default output device.
+/// If there isn't a device the samples are just thrown away
+pub fn open_audio(wav_path: Option<String>) -> (AudioOutput, Box<dyn AudioSink + Send>) {
+    if let Some(path) = wav_path {
+        match WavSink::create(&path) {
+            Ok(sink) => return (AudioOutput::silent(), Box::new(sink)),
+            Err(e) => eprintln!("couldn't create {path} => {e:?}"),
+        }
+    }
+
+    #[cfg(feature = "audio")]
+    match device::open_device() {
+        Ok((stream, sink)) => return (AudioOutput { _stream: Some(stream) }, Box::new(sink)),
+        Err(e) => eprintln!("no audio device, sound is disabled => {e}"),
+    }
+
+    return (AudioOutput::silent(), Box::new(NullSink));
+}
+impl AudioOutput {
+    fn silent() -> Self {
+        Self {
+            #[cfg(feature = "audio")]
+            _stream: None,
+        }
+    }
+}
+
+pub struct NullSink;
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 { DEFAULT_SAMPLE_RATE }
+    fn push(&mut self, _samples: &[i16]) {}
+    fn fill_level(&self) -> Option<f32> { None }
+}
+
+const WAV_HEADER_LENGTH: u32 = 44;
+// 16 bit stereo
+const WAV_BYTES_PER_SECOND: u32 = DEFAULT_SAMPLE_RATE * 4;
+pub struct WavSink {
+    writer: BufWriter<File>,
+    data_length: u32,
+}
+impl WavSink {
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        let mut sink = Self {
+            writer: BufWriter::new(File::create(path)?),
+            data_length: 0,
+        };
+        sink.write_header()?;
+        Ok(sink)
+    }
+
+    /// the lengths aren't known until the end, so this gets written again every so often.
+    /// The emulator thread never finishes, so waiting for it to be dropped isn't enough
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let channels: u16 = 2;
+        let bits_per_sample: u16 = 16;
+        let block_align = channels * bits_per_sample / 8;
+        let byte_rate = DEFAULT_SAMPLE_RATE * block_align as u32;
+
+        let w = &mut self.writer;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(WAV_HEADER_LENGTH - 8 + self.data_length).to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?; // PCM
+        w.write_all(&channels.to_le_bytes())?;
+        w.write_all(&DEFAULT_SAMPLE_RATE.to_le_bytes())?;
+        w.write_all(&byte_rate.to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&bits_per_sample.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&self.data_length.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// fixes up the lengths in the header and makes sure everything so far is in the file
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+impl AudioSink for WavSink {
+    fn sample_rate(&self) -> u32 { DEFAULT_SAMPLE_RATE }
+    fn push(&mut self, samples: &[i16]) {
+        for sample in samples {
+            if self.writer.write_all(&sample.to_le_bytes()).is_err() {
+                return;
+            }
+            self.data_length += 2;
+
+            // once a second of audio
+            if self.data_length.is_multiple_of(WAV_BYTES_PER_SECOND) {
+                let _ = self.finish();
+            }
+        }
+    }
+    fn fill_level(&self) -> Option<f32> { None }
+}
+impl Drop for WavSink {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(feature = "audio")]
+mod device {
+    use super::AudioSink;
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{FromSample, SampleFormat, SizedSample};
+    use parking_lot::Mutex;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // 200ms worth of samples, rate control tries to keep it half full
+    const QUEUE_SECONDS_DIVISOR: usize = 5;
+    // if the device hasn't made room by then it has probably stalled (or been unplugged)
+    const MAX_PUSH_WAIT: Duration = Duration::from_millis(10);
+
+    struct SharedQueue {
+        samples: Mutex<VecDeque<i16>>,
+        capacity: usize,
+    }
+
+    pub struct CpalSink {
+        queue: Arc<SharedQueue>,
+        sample_rate: u32,
+    }
+    impl AudioSink for CpalSink {
+        fn sample_rate(&self) -> u32 { self.sample_rate }
+
+        /// the device is the clock here, so rather than dropping samples
+        /// the emulator waits for it to make room, for a while at least
+        fn push(&mut self, samples: &[i16]) {
+            let needed = samples.len().min(self.queue.capacity);
+            let give_up = Instant::now() + MAX_PUSH_WAIT;
+            while self.queue.samples.lock().len() + needed > self.queue.capacity && Instant::now() < give_up {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            let mut queue = self.queue.samples.lock();
+            queue.extend(samples);
+            while queue.len() > self.queue.capacity {
+                queue.pop_front();
+            }
+        }
+        fn fill_level(&self) -> Option<f32> {
+            let len = self.queue.samples.lock().len();
+            Some(len as f32 / self.queue.capacity as f32)
+        }
+    }
+
+    pub fn open_device() -> Result<(cpal::Stream, CpalSink), String> {
+        let host = cpal::default_host();
+        let device = host.default_output_device().ok_or("no default output device")?;
+        let supported = device.default_output_config().map_err(|e| e.to_string())?;
+
+        let sample_format = supported.sample_format();
+        let config: cpal::StreamConfig = supported.into();
+        let sample_rate = config.sample_rate.0;
+        let queue = Arc::new(SharedQueue {
+            samples: Mutex::new(VecDeque::new()),
+            capacity: sample_rate as usize / QUEUE_SECONDS_DIVISOR * 2,
+        });
+
+        let stream = match sample_format {
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone()),
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone()),
+            other => return Err(format!("unsupported sample format {other:?}")),
+        }?;
+        stream.play().map_err(|e| e.to_string())?;
+
+        Ok((stream, CpalSink { queue, sample_rate }))
+    }
+
+    fn build_stream<T: SizedSample + FromSample<i16>>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        queue: Arc<SharedQueue>,
+    ) -> Result<cpal::Stream, String> {
+        let channels = config.channels as usize;
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _| write_frames(data, channels, &queue),
+            |e| eprintln!("audio stream error => {e:?}"),
+            None,
+        ).map_err(|e| e.to_string())
+    }
+
+    fn write_frames<T: SizedSample + FromSample<i16>>(data: &mut [T], channels: usize, queue: &SharedQueue) {
+        let mut samples = queue.samples.lock();
+        for frame in data.chunks_mut(channels) {
+            // if the emulator has fallen behind silence is better than repeating anything
+            let (left, right) = match samples.len() >= 2 {
+                true => (samples.pop_front().unwrap(), samples.pop_front().unwrap()),
+                false => (0, 0),
+            };
+
+            for (i, out) in frame.iter_mut().enumerate() {
+                let sample = match (channels, i) {
+                    (1, _) => ((left as i32 + right as i32) / 2) as i16,
+                    (_, 0) => left,
+                    (_, 1) => right,
+                    _ => 0,
+                };
+                *out = T::from_sample(sample);
+            }
+        }
+    }
+}
@@ -2,13 +2,15 @@
 mod memory_widget;
 mod instruction_widget;
 mod cpu_widget;
+mod sound_widget;
 
-use std::sync::{mpsc::Sender, Arc};
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc::Sender, Arc};
 use cpu_widget::CpuWidget;
 use egui::{ViewportBuilder, ViewportClass, ViewportId};
 use instruction_widget::InstructionWidget;
 use memory_widget::MemoryWidget;
 use parking_lot::RwLock;
+use sound_widget::SoundWidget;
 use gba_core::Emulator;
 use crate::emulator::{EmulatorSend, EmulatorState};
 
@@ -18,21 +20,38 @@ pub struct Debugger {
     mem_widget: MemoryWidget,
     ins_widget: InstructionWidget,
     cpu_widget: CpuWidget,
+    sound_widget: SoundWidget,
     show_vram: bool,
     pause: bool,
     delay: String,
+    save_path: String,
+    // the cart sensors, sent over whenever a slider moves
+    light_level: u8,
+    tilt: (f32, f32),
+    rotation: f32,
+    rumbling: Arc<AtomicBool>,
 }
 impl Debugger {
     pub fn new(emulator: Arc<RwLock<Emulator>>, inp_send: Sender<EmulatorSend>) -> Self { 
+        let rumbling = Arc::new(AtomicBool::new(false));
+        let rumble_flag = rumbling.clone();
+        emulator.write().set_rumble_callback(Box::new(move |active| rumble_flag.store(active, Ordering::Relaxed)));
+
         Self { 
             emulator_ref: emulator,
             inp_send,
             mem_widget: MemoryWidget::new(),
             ins_widget: InstructionWidget::new(),
             cpu_widget: CpuWidget::new(),
+            sound_widget: SoundWidget::new(),
             show_vram: false,
             pause: false,
             delay: String::from("0"),
+            save_path: String::new(),
+            light_level: 0,
+            tilt: (0., 0.),
+            rotation: 0.,
+            rumbling,
         } 
     }
 
@@ -42,12 +61,17 @@ impl Debugger {
             ViewportBuilder::default()
                 .with_title("control panel")
                 .with_resizable(false)
-                .with_inner_size([340., 140.])
+                .with_inner_size([340., 320.])
                 .with_position([780., 575.]), 
             |ctx, class| {
                 assert!(class == ViewportClass::Immediate);
                 egui::CentralPanel::default().show(&ctx, |ui| {
                     ui.label("Debug panel");
+                    let emulator = self.emulator_ref.read();
+                    let header = &emulator.header;
+                    ui.label(format!("{} ({}{}) v{}", header.title, header.game_code, header.maker_code, header.version));
+                    ui.label(format!("Save type: {:?}", emulator.save_type()));
+                    drop(emulator);
 
                     // menu to create new windows with information
                     ui.columns(2, |columns| {
@@ -55,6 +79,7 @@ impl Debugger {
                         columns[0].checkbox(&mut self.show_vram, "Show VRAM panel");
                         columns[1].checkbox(&mut self.cpu_widget.open, "Show CPU panel");
                         columns[1].checkbox(&mut self.ins_widget.open, "Show instruction panel");
+                        columns[0].checkbox(&mut self.sound_widget.open, "Show sound panel");
                     });
 
                     ui.separator();
@@ -78,14 +103,45 @@ impl Debugger {
                             self.inp_send.send(EmulatorSend::StateUpdate(EmulatorState::Step)).unwrap();
                         }
                     });
+
+                    // moving saves between emulators, the .sav next to the ROM is handled already
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut self.save_path).hint_text("save path").desired_width(180.));
+                        if ui.button("Import").clicked() {
+                            self.inp_send.send(EmulatorSend::ImportSave(self.save_path.clone().into())).unwrap();
+                        }
+                        if ui.button("Export").clicked() {
+                            self.inp_send.send(EmulatorSend::ExportSave(self.save_path.clone().into())).unwrap();
+                        }
+                    });
+
+                    // only does anything for carts with the matching hardware
+                    ui.separator();
+                    if ui.add(egui::Slider::new(&mut self.light_level, 0..=0xFF).text("Light level")).changed() {
+                        self.inp_send.send(EmulatorSend::LightLevel(self.light_level)).unwrap();
+                    }
+                    let tilt_x = ui.add(egui::Slider::new(&mut self.tilt.0, -1.0..=1.0).text("Tilt X"));
+                    let tilt_y = ui.add(egui::Slider::new(&mut self.tilt.1, -1.0..=1.0).text("Tilt Y"));
+                    if tilt_x.changed() || tilt_y.changed() {
+                        self.inp_send.send(EmulatorSend::Tilt(self.tilt.0, self.tilt.1)).unwrap();
+                    }
+                    if ui.add(egui::Slider::new(&mut self.rotation, -1.0..=1.0).text("Rotation")).changed() {
+                        self.inp_send.send(EmulatorSend::Rotation(self.rotation)).unwrap();
+                    }
+                    let rumble_text = match self.rumbling.load(Ordering::Relaxed) {
+                        true => "Rumble: on",
+                        false => "Rumble: off",
+                    };
+                    ui.label(rumble_text);
                 });
             }
         );
 
         let emulator = self.emulator_ref.read();
-        if self.cpu_widget.open { self.cpu_widget.draw(ctx, &emulator.cpu); }
+        if self.cpu_widget.open { self.cpu_widget.draw(ctx, &emulator.cpu, &emulator.symbols); }
         if self.ins_widget.open { self.ins_widget.draw(ctx)}
         if self.show_vram {eprintln!("not done yet"); self.show_vram = false;}
         if self.mem_widget.open { self.mem_widget.draw(&emulator.bus.mem, ctx) }
+        if self.sound_widget.open { self.sound_widget.draw(ctx, &emulator.apu, &emulator.bus.mem, &self.inp_send) }
     }
 }
\ No newline at end of file
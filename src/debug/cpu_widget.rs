@@ -2,6 +2,7 @@
 
 use egui::{TextEdit, ViewportBuilder, ViewportClass, ViewportId};
 use gba_core::cpu::{convert_psr_u32, Cpu};
+use gba_core::elf::SymbolTable;
 
 pub struct CpuWidget {
     pub open: bool,
@@ -13,7 +14,7 @@ impl CpuWidget {
         }
     }
 
-    pub fn draw(&self, ctx: &egui::Context, cpu: &Cpu) {
+    pub fn draw(&self, ctx: &egui::Context, cpu: &Cpu, symbols: &SymbolTable) {
         let mut cpsr: String = format!("{:032b}", convert_psr_u32(&cpu.cpsr));
 
         ctx.show_viewport_immediate(
@@ -35,6 +36,12 @@ impl CpuWidget {
                         }
                     });
 
+                    // only ELFs have symbols to show
+                    if !symbols.is_empty() {
+                        ui.label(format!("pc: {}", symbols.describe(cpu.get_register(15))));
+                        ui.label(format!("lr: {}", symbols.describe(cpu.get_register(14))));
+                    }
+
                     ui.separator();
                     ui.monospace("       NZCV--------------------IFT43210"); // hmm yes very good ui
                     ui.horizontal(|ui| {
@@ -0,0 +1,224 @@
+#![cfg(feature = "debug")]
+
+use std::sync::mpsc::Sender;
+use egui::{pos2, vec2, Color32, Sense, Shape, Stroke, Ui, ViewportBuilder, ViewportClass, ViewportId};
+use gba_core::apu::{Apu, ApuRegisters, SoundChannel, SCOPE_LENGTH, SOUND_CHANNELS};
+use gba_core::mem::memory::InternalMemory;
+use crate::emulator::EmulatorSend;
+
+const SCOPE_HEIGHT: f32 = 40.;
+
+pub struct SoundWidget {
+    pub open: bool,
+}
+impl SoundWidget {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+        }
+    }
+
+    pub fn draw(&self, ctx: &egui::Context, apu: &Apu, mem: &InternalMemory, inp_send: &Sender<EmulatorSend>) {
+        ctx.show_viewport_immediate(
+            ViewportId::from_hash_of("sound panel"),
+            ViewportBuilder::default()
+                .with_title("sound")
+                .with_position([1130., 0.])
+                .with_inner_size([420., 900.])
+                .with_resizable(false),
+            |ctx, class| {
+                assert!(class == ViewportClass::Immediate);
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        draw_master(ui, mem);
+                        for channel in SOUND_CHANNELS {
+                            ui.separator();
+                            draw_channel(ui, channel, apu, mem, inp_send);
+                        }
+                    });
+                });
+            }
+        );
+    }
+}
+
+fn draw_master(ui: &mut Ui, mem: &InternalMemory) {
+    let soundcnt_l = mem.sys_read_u16(ApuRegisters::SoundCntL as u32);
+    let soundcnt_h = mem.sys_read_u16(ApuRegisters::SoundCntH as u32);
+    let soundcnt_x = mem.sys_read_u16(ApuRegisters::SoundCntX as u32);
+    let soundbias = mem.sys_read_u16(ApuRegisters::SoundBias as u32);
+
+    let psg_volume = match soundcnt_h & 0x3 {
+        0 => "25%",
+        1 => "50%",
+        2 => "100%",
+        _ => "prohibited",
+    };
+    ui.heading("Master");
+    ui.monospace(format!(
+        "enabled: {}  PSG volume: {psg_volume}  L: {}/8  R: {}/8",
+        (soundcnt_x >> 7) & 1 == 1,
+        ((soundcnt_l >> 4) & 0x7) + 1,
+        (soundcnt_l & 0x7) + 1,
+    ));
+    ui.monospace(format!(
+        "bias: {:03X}  resolution: {} bits ({}Hz)",
+        soundbias & 0x3FE,
+        9 - ((soundbias >> 14) & 0x3),
+        32768 << ((soundbias >> 14) & 0x3),
+    ));
+}
+
+fn draw_channel(ui: &mut Ui, channel: SoundChannel, apu: &Apu, mem: &InternalMemory, inp_send: &Sender<EmulatorSend>) {
+    ui.horizontal(|ui| {
+        ui.heading(channel.name());
+
+        let mut muted = apu.is_muted(channel);
+        if ui.checkbox(&mut muted, "mute").changed() {
+            inp_send.send(EmulatorSend::Mute(channel, muted)).unwrap();
+        }
+        let mut soloed = apu.is_soloed(channel);
+        if ui.checkbox(&mut soloed, "solo").changed() {
+            inp_send.send(EmulatorSend::Solo(channel, soloed)).unwrap();
+        }
+    });
+
+    for line in decode_registers(channel, apu, mem) {
+        ui.monospace(line);
+    }
+
+    // the PSG channels only go up to ±15, the FIFOs are signed 8-bit
+    let range = match channel {
+        SoundChannel::FifoA | SoundChannel::FifoB => 128.,
+        _ => 15.,
+    };
+    draw_scope(ui, apu.scopes[channel as usize].samples(), range);
+}
+
+fn draw_scope(ui: &mut Ui, samples: impl Iterator<Item = i16>, range: f32) {
+    let width = ui.available_width();
+    let (response, painter) = ui.allocate_painter(vec2(width, SCOPE_HEIGHT), Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0., Color32::BLACK);
+    painter.line_segment([rect.left_center(), rect.right_center()], Stroke::new(1., Color32::DARK_GRAY));
+
+    let step = rect.width() / (SCOPE_LENGTH - 1) as f32;
+    let points = samples.enumerate().map(|(i, sample)| {
+        let y = rect.center().y - (sample as f32 / range) * (rect.height() / 2.);
+        pos2(rect.left() + i as f32 * step, y.clamp(rect.top(), rect.bottom()))
+    }).collect();
+    painter.add(Shape::line(points, Stroke::new(1., Color32::LIGHT_GREEN)));
+}
+
+fn decode_registers(channel: SoundChannel, apu: &Apu, mem: &InternalMemory) -> Vec<String> {
+    use ApuRegisters::*;
+    let read = |register: ApuRegisters| mem.sys_read_u16(register as u32);
+    let soundcnt_l = read(SoundCntL);
+    let soundcnt_h = read(SoundCntH);
+
+    // which sides of SOUNDCNT_L each PSG channel goes to
+    let psg_sides = |i: u16| format!(
+        "L: {}  R: {}",
+        (soundcnt_l >> (12 + i)) & 1 == 1,
+        (soundcnt_l >> (8 + i)) & 1 == 1,
+    );
+
+    match channel {
+        SoundChannel::Square1 | SoundChannel::Square2 => {
+            let (duty_reg, frequency_reg, enabled, i) = match channel {
+                SoundChannel::Square1 => (read(Sound1CntH), read(Sound1CntX), apu.square1.enabled, 0),
+                _ => (read(Sound2CntL), read(Sound2CntH), apu.square2.enabled, 1),
+            };
+            let duty = match (duty_reg >> 6) & 0x3 {
+                0 => "12.5%",
+                1 => "25%",
+                2 => "50%",
+                _ => "75%",
+            };
+            let frequency = frequency_reg & 0x7FF;
+            let mut lines = vec![
+                format!("playing: {enabled}  {}", psg_sides(i)),
+                format!("duty: {duty}  length: {}  length enabled: {}", duty_reg & 0x3F, (frequency_reg >> 14) & 1 == 1),
+                decode_envelope(duty_reg),
+                format!("frequency: {frequency:03X} ({}Hz)", 131072 / (2048 - frequency as u32)),
+            ];
+            if channel == SoundChannel::Square1 {
+                let sweep = read(Sound1CntL);
+                lines.push(format!(
+                    "sweep time: {}  shift: {}  {}",
+                    (sweep >> 4) & 0x7,
+                    sweep & 0x7,
+                    match (sweep >> 3) & 1 == 1 { true => "decrease", false => "increase" },
+                ));
+            }
+            lines
+        }
+        SoundChannel::Wave => {
+            let select = read(Sound3CntL);
+            let length_volume = read(Sound3CntH);
+            let frequency = read(Sound3CntX) & 0x7FF;
+            let volume = match ((length_volume >> 15) & 1 == 1, (length_volume >> 13) & 0x3) {
+                (true, _) => "75%",
+                (false, 0) => "0%",
+                (false, 1) => "100%",
+                (false, 2) => "50%",
+                (false, _) => "25%",
+            };
+            vec![
+                format!("playing: {}  {}", apu.wave.enabled, psg_sides(2)),
+                format!(
+                    "playback: {}  banks: {}  bank: {}",
+                    (select >> 7) & 1 == 1,
+                    match (select >> 5) & 1 == 1 { true => "2 (64 samples)", false => "1 (32 samples)" },
+                    (select >> 6) & 1,
+                ),
+                format!("volume: {volume}  length: {}", length_volume & 0xFF),
+                format!("frequency: {frequency:03X} ({}Hz sample rate)", 2097152 / (2048 - frequency as u32)),
+            ]
+        }
+        SoundChannel::Noise => {
+            let envelope_reg = read(Sound4CntL);
+            let frequency_reg = read(Sound4CntH);
+            let ratio = (frequency_reg & 0x7) as f32;
+            let shift = (frequency_reg >> 4) & 0xF;
+            let frequency = 524288. / ratio.max(0.5) / (2 << shift) as f32;
+            vec![
+                format!("playing: {}  {}", apu.noise.enabled, psg_sides(3)),
+                format!("length: {}  length enabled: {}", envelope_reg & 0x3F, (frequency_reg >> 14) & 1 == 1),
+                decode_envelope(envelope_reg),
+                format!(
+                    "ratio: {}  shift: {shift}  width: {} bits ({frequency:.0}Hz)",
+                    frequency_reg & 0x7,
+                    match (frequency_reg >> 3) & 1 == 1 { true => 7, false => 15 },
+                ),
+            ]
+        }
+        SoundChannel::FifoA | SoundChannel::FifoB => {
+            let fifo = channel as usize - SoundChannel::FifoA as usize;
+            let bits = soundcnt_h >> (8 + fifo * 4);
+            vec![
+                format!(
+                    "queued: {}/32  current: {}",
+                    mem.fifos[fifo].len(),
+                    mem.fifos[fifo].current,
+                ),
+                format!(
+                    "volume: {}  timer: {}  L: {}  R: {}",
+                    match (soundcnt_h >> (2 + fifo)) & 1 == 1 { true => "100%", false => "50%" },
+                    (bits >> 2) & 1,
+                    (bits >> 1) & 1 == 1,
+                    bits & 1 == 1,
+                ),
+            ]
+        }
+    }
+}
+
+fn decode_envelope(register: u16) -> String {
+    format!(
+        "envelope volume: {}  step: {}  {}",
+        (register >> 12) & 0xF,
+        (register >> 8) & 0x7,
+        match (register >> 11) & 1 == 1 { true => "increase", false => "decrease" },
+    )
+}
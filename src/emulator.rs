@@ -1,7 +1,14 @@
-use gba_core::{joypad::{self, joypad_press, joypad_release}, run_single_step, Emulator};
+use gba_core::{apu::SoundChannel, joypad::{self, joypad_press, joypad_release}, run_single_step, Emulator};
 use std::{sync::{mpsc::{Receiver, SyncSender}, Arc}, thread, time::Duration};
 use egui::Key;
 use parking_lot::RwLock;
+use crate::audio::AudioSink;
+use crate::save::SaveFlusher;
+use std::path::PathBuf;
+
+// how far the sample rate can be pushed either way to keep the audio queue half full,
+// small enough that the change in pitch can't be heard
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
 
 fn convert_to_joypad(code: Key) -> joypad::Button {
     use joypad::Button;
@@ -24,6 +31,14 @@ fn convert_to_joypad(code: Key) -> joypad::Button {
 pub enum EmulatorSend {
     StateUpdate(EmulatorState),
     Event(Key, bool),
+    Mute(SoundChannel, bool),
+    Solo(SoundChannel, bool),
+    ImportSave(PathBuf),
+    ExportSave(PathBuf),
+    // inputs for the sensors some carts have, see `Emulator::set_light_level` and co
+    LightLevel(u8),
+    Tilt(f32, f32),
+    Rotation(f32),
 }
 #[derive(Debug, Clone, Copy)]
 pub enum EmulatorState {
@@ -36,9 +51,13 @@ pub fn run_emulator(
     emulator_arc: Arc<RwLock<Emulator>>,
     redraw_send: SyncSender<Vec<u16>>,
     inp_recv: Receiver<EmulatorSend>,
+    mut audio_sink: Box<dyn AudioSink + Send>,
+    save_path: PathBuf,
 ) {
+    let mut save_flusher = SaveFlusher::new(save_path, &emulator_arc.read());
     let mut state = EmulatorState::Pause;
     let mut drew_last_time = false;
+    let mut samples = vec![0; audio_sink.sample_rate() as usize / 2];
     loop {
         let redraw_needed = update_emulator(&emulator_arc, &mut state, &mut drew_last_time);
         if redraw_needed {
@@ -47,6 +66,18 @@ pub fn run_emulator(
             drew_last_time = true;
         }
 
+        // once a frame is enough, pushing can block so it's done without holding the lock
+        if redraw_needed {
+            let amount = emulator_arc.write().drain_audio(&mut samples);
+            audio_sink.push(&samples[..amount]);
+
+            if let Some(fill) = audio_sink.fill_level() {
+                let ratio = 1. + (0.5 - fill as f64) * 2. * MAX_RATE_ADJUSTMENT;
+                emulator_arc.write().set_audio_rate_ratio(ratio);
+            }
+            save_flusher.update(&emulator_arc.read());
+        }
+
         if let Ok(i) = inp_recv.try_recv() {
             match i {
                 EmulatorSend::Event(key, pressed) => {
@@ -58,6 +89,21 @@ pub fn run_emulator(
                     }
                 }
                 EmulatorSend::StateUpdate(new_state) => state = new_state,
+                EmulatorSend::Mute(channel, muted) => emulator_arc.write().apu.set_muted(channel, muted),
+                EmulatorSend::Solo(channel, soloed) => emulator_arc.write().apu.set_soloed(channel, soloed),
+                EmulatorSend::ImportSave(path) => {
+                    if let Err(e) = emulator_arc.write().import_save(&path) {
+                        eprintln!("couldn't import {} => {e:?}", path.display());
+                    }
+                }
+                EmulatorSend::ExportSave(path) => {
+                    if let Err(e) = emulator_arc.read().export_save(&path) {
+                        eprintln!("couldn't export {} => {e:?}", path.display());
+                    }
+                }
+                EmulatorSend::LightLevel(level) => emulator_arc.write().set_light_level(level),
+                EmulatorSend::Tilt(x, y) => emulator_arc.write().set_tilt(x, y),
+                EmulatorSend::Rotation(rotation) => emulator_arc.write().set_rotation(rotation),
             }
         }
     }
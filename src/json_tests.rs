@@ -59,10 +59,10 @@ impl JsonMemory {
     }
 }
 impl CpuInterface for JsonMemory {
-    fn read_u16(&self, address: u32) -> u16 { self.read(2, address) as u16 }
-    fn read_u32_rotated(&self, address: u32) -> u32 { self.read(4, address).rotate_right((address & 0b11) * 8) }
-    fn read_u32_unrotated(&self, address: u32) -> u32 { self.read(4, address) }
-    fn read_u8(&self, address: u32) ->  u8  { self.read(1, address) as u8  }
+    fn read_u16(&mut self, address: u32) -> u16 { self.read(2, address) as u16 }
+    fn read_u32_rotated(&mut self, address: u32) -> u32 { self.read(4, address).rotate_right((address & 0b11) * 8) }
+    fn read_u32_unrotated(&mut self, address: u32) -> u32 { self.read(4, address) }
+    fn read_u8(&mut self, address: u32) ->  u8  { self.read(1, address) as u8  }
     fn write_u16(&mut self, address: u32, data: u16) { self.write(2, address, data as u64); }
     fn write_u32(&mut self, address: u32, data: u32) { self.write(4, address, data as u64); }
     fn write_u8(&mut self, address: u32, data: u8)   { self.write(1, address, data as u64); }
@@ -171,6 +171,9 @@ fn init_single_test(start: bool, test: &Value) -> Cpu {
         spsr: spsr.try_into().unwrap(),
         barrel_shifter: false,
         fde,
+        hle_bios: false,
+        swi_trace: None,
+        intr_waiting: None,
     };
     return cpu
 }
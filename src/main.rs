@@ -5,14 +5,19 @@ use debug::Debugger;
 mod json_tests;
 
 mod emulator;
+mod audio;
+mod save;
+use audio::open_audio;
 use egui::{Color32, Event, Frame, TextureOptions};
 use emulator::{run_emulator, EmulatorSend};
 use parking_lot::RwLock;
 use gba_core::Emulator;
+use gba_core::mem::carts::TimeSource;
 
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{mpsc, Arc};
 use std::env;
+use std::path::Path;
 use std::thread;
 
 fn main() {
@@ -23,25 +28,71 @@ fn main() {
 
     let file = env::args().nth(1).unwrap();
     let rom_path = format!("roms/{file}");
-    let from_bios = cfg!(feature = "from-bios");
-    let emulator_ref = Arc::new(RwLock::new(Emulator::new(&rom_path, from_bios)));
+    // the `from-bios` feature just changes what happens without the flag
+    let from_bios = cfg!(feature = "from-bios") || has_flag("--from-bios");
+
+    // the output stream has to live as long as the window does
+    let (_audio_output, audio_sink) = open_audio(flag_value("--wav"));
+    // a patch next to the ROM with the same name gets used without this
+    let patch_path = flag_value("--patch");
+    // multiboot images don't have a cart, they get run from EWRAM instead
+    let is_multiboot = has_flag("--multiboot") || rom_path.ends_with(".mb");
+    let emulator = if is_multiboot {
+        Emulator::multiboot(Path::new(&rom_path))
+    } else if rom_path.ends_with(".elf") {
+        Emulator::from_elf(Path::new(&rom_path))
+    } else {
+        Emulator::new(Path::new(&rom_path), patch_path.as_ref().map(Path::new), from_bios)
+    };
+    let mut emulator = match emulator {
+        Ok(emulator) => emulator,
+        Err(e) => {
+            eprintln!("couldn't load {rom_path} => {e}");
+            return;
+        }
+    };
+    emulator.set_sample_rate(audio_sink.sample_rate());
+    emulator.set_hle_bios(has_flag("--hle-bios"));
+    emulator.set_swi_trace(has_flag("--trace-swi"));
+    for warning in &emulator.header.warnings {
+        eprintln!("ROM header: {warning}");
+    }
+    let title = format!("{} ({})", emulator.header.title, emulator.header.game_code);
+
+    // the RTC follows the host's clock unless it is told otherwise
+    if let Some(time) = flag_value("--rtc-fixed").and_then(|t| t.parse().ok()) {
+        emulator.set_rtc_time_source(TimeSource::Fixed(time));
+    } else if let Some(offset) = flag_value("--rtc-offset").and_then(|o| o.parse().ok()) {
+        emulator.set_rtc_time_source(TimeSource::Offset(offset));
+    }
+
+    // saves live next to the ROM with the same name
+    let save_path = Path::new(&rom_path).with_extension("sav");
+    if save_path.exists() {
+        if let Err(e) = emulator.import_save(&save_path) {
+            eprintln!("couldn't load {} => {e:?}", save_path.display());
+        }
+    }
+    let emulator_ref = Arc::new(RwLock::new(emulator));
 
     let (emu_send, emu_recv) = mpsc::channel::<EmulatorSend>();
     let (draw_send, draw_recv) = mpsc::sync_channel::<Vec<u16>>(1);
 
     let emulator = emulator_ref.clone();
+    let emulator_save_path = save_path.clone();
     thread::Builder::new().name("emulator_thread".into()).spawn(|| {
-        run_emulator(emulator, draw_send, emu_recv);
+        run_emulator(emulator, draw_send, emu_recv, audio_sink, emulator_save_path);
     }).unwrap();
     
     let debugger;
     match cfg!(feature = "debug") {
-        true => debugger = Some(Debugger::new(emulator_ref, emu_send.clone())),
+        true => debugger = Some(Debugger::new(emulator_ref.clone(), emu_send.clone())),
         false => debugger = None,
     }
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
+            .with_title(title)
             .with_resizable(false)
             .with_inner_size([SCREEN_WIDTH as f32 * SCREEN_RATIO, SCREEN_HEIGHT as f32 * SCREEN_RATIO])
             .with_position([780., 0.]),
@@ -53,6 +104,26 @@ fn main() {
         options, 
         Box::new(|_| Ok(Box::new(emulator_app)))
     ).unwrap();
+
+    // whatever hasn't been flushed yet would be lost otherwise
+    let emulator = emulator_ref.read();
+    if let Err(e) = emulator.export_save(&save_path) {
+        eprintln!("couldn't write {} => {e:?}", save_path.display());
+    }
+}
+
+/// the argument straight after `flag`, if it was given
+fn flag_value(flag: &str) -> Option<String> {
+    let mut args = env::args().skip(2);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+    }
+    return None;
+}
+fn has_flag(flag: &str) -> bool {
+    env::args().skip(2).any(|arg| arg == flag)
 }
 
 struct EmulatorApp {
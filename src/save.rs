@@ -0,0 +1,43 @@
+use gba_core::Emulator;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// games write their saves a byte at a time, so this waits
+// for them to stop before writing the file
+const FLUSH_DELAY: Duration = Duration::from_secs(1);
+
+/// keeps the .sav file up to date while the emulator runs
+pub struct SaveFlusher {
+    path: PathBuf,
+    seen_writes: u64,
+    saved_writes: u64,
+    last_write: Instant,
+}
+impl SaveFlusher {
+    pub fn new(path: PathBuf, emulator: &Emulator) -> Self {
+        let writes = emulator.save_writes();
+        Self {
+            path,
+            seen_writes: writes,
+            saved_writes: writes,
+            last_write: Instant::now(),
+        }
+    }
+
+    pub fn update(&mut self, emulator: &Emulator) {
+        let writes = emulator.save_writes();
+        if writes != self.seen_writes {
+            self.seen_writes = writes;
+            self.last_write = Instant::now();
+            return;
+        }
+        if writes == self.saved_writes || self.last_write.elapsed() < FLUSH_DELAY {
+            return;
+        }
+
+        match emulator.export_save(&self.path) {
+            Ok(_) => self.saved_writes = writes,
+            Err(e) => eprintln!("couldn't write {} => {e:?}", self.path.display()),
+        }
+    }
+}